@@ -0,0 +1,40 @@
+//! Times find_longest_paths on fixed regions of the bundled map, run with `cargo bench`. Names
+//! given after `--` only run the regions starting at those systems.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use frontier_route::*;
+
+/// Regions routed from these systems, the same ones as the README examples.
+const STARTS: [&str; 3] = ["G:3N86", "I.EXP.NJ7", "O.32R.QY4"];
+
+/// Each region is searched until this much time is spent on it, and at least MIN_RUNS times.
+const BUDGET: Duration = Duration::from_secs(5);
+const MIN_RUNS: usize = 3;
+
+fn main() {
+    let filter = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect::<Vec<_>>();
+    let router = Router::from_map(concat!(env!("CARGO_MANIFEST_DIR"), "/map.json"), &mut Vec::new())
+        .expect("Bundled map loads");
+
+    for name in STARTS.into_iter().filter(|name| filter.is_empty() || filter.iter().any(|f| f == name)) {
+        let start = router.graph()[find_system(router.graph(), name).expect("Start system is on the map")].id;
+        let region = router.region(start, &HashSet::new()).expect("Start has a region");
+
+        // The first run warms the caches and isn't counted
+        let jumps = find_longest_paths(region.clone(), start, &SearchOptions::default()).len();
+
+        let mut times = Vec::new();
+        let started = Instant::now();
+        while times.len() < MIN_RUNS || started.elapsed() < BUDGET {
+            let run = Instant::now();
+            std::hint::black_box(find_longest_paths(region.clone(), start, &SearchOptions::default()));
+            times.push(run.elapsed());
+        }
+
+        let best = times.iter().min().expect("Ran at least once");
+        let mean = times.iter().sum::<Duration>() / times.len() as u32;
+        println!("{:<10} {:>3} systems {:>3} connections {:>4} jumps   best {:>9.3}ms   mean {:>9.3}ms   ({} runs)",
+            name, region.node_count(), region.edge_count(), jumps, best.as_secs_f64() * 1000.0, mean.as_secs_f64() * 1000.0, times.len());
+    }
+}
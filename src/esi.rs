@@ -0,0 +1,260 @@
+//! Minimal client for the EVE Swagger Interface universe endpoints.
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::time::Duration;
+
+pub const ESI_BASE: &str = "https://esi.evetech.net/latest";
+
+/// Requests in flight at once while downloading the map.
+pub const ESI_CONCURRENCY: usize = 8;
+
+/// Tries at each request before a transient failure is given up on.
+pub const ESI_ATTEMPTS: u32 = 5;
+
+/// Once fewer errors than this are left in ESI's error limit window, requests wait for the window
+/// to reset, as ESI blocks clients that use up the allowance.
+pub const ESI_ERROR_MARGIN: u32 = 10;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EsiSystem {
+    #[serde(default)]
+    pub system_id: u32,
+    pub name: String,
+    #[serde(default)]
+    pub constellation_id: u32,
+    #[serde(default)]
+    pub security_status: f32,
+    pub position: Option<EsiPosition>,
+    #[serde(default)]
+    pub stargates: Vec<u32>,
+    #[serde(default)]
+    pub stations: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct EsiPosition {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EsiConstellation {
+    pub region_id: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EsiName {
+    pub id: u32,
+    pub name: String,
+}
+
+/// When a fetched map was last checked against ESI, kept beside the map file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheInfo {
+    pub etag: Option<String>,
+    /// RFC 2822 time after which ESI may have a newer system list
+    pub expires: Option<String>,
+}
+
+/// What `fetch_map` did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fetched {
+    /// The cached map hasn't expired yet
+    Fresh,
+    /// ESI's system list is unchanged since the cached map was fetched
+    Unchanged,
+    /// The map was downloaded, with this many systems
+    Downloaded(usize),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EsiStargate {
+    pub destination: EsiDestination,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EsiDestination {
+    pub system_id: u32,
+}
+
+fn header<T: std::str::FromStr>(response: &ureq::Response, name: &str) -> Option<T> {
+    response.header(name).and_then(|value| value.parse().ok())
+}
+
+/// Sends a request, with `body` as JSON if given, retrying connection failures, server errors and
+/// error limiting with a growing backoff, and waiting out the error limit window whenever little
+/// of it is left.
+fn send(request: &ureq::Request, body: Option<&[u32]>) -> Result<ureq::Response, Box<ureq::Error>> {
+    let mut attempt = 1;
+    loop {
+        let result = match body {
+            Some(body) => request.clone().send_json(body),
+            None => request.clone().call(),
+        };
+        let response = match &result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => Some(response),
+            Err(ureq::Error::Transport(_)) => None,
+        };
+        let limited = response.and_then(|response| {
+            let remain = header::<u32>(response, "X-ESI-Error-Limit-Remain")?;
+            let reset = header::<u64>(response, "X-ESI-Error-Limit-Reset")?;
+            (remain < ESI_ERROR_MARGIN).then_some(Duration::from_secs(reset))
+        });
+        let transient = match &result {
+            Ok(_) => false,
+            Err(ureq::Error::Status(code, _)) => *code == 420 || *code == 429 || *code >= 500,
+            Err(ureq::Error::Transport(_)) => true,
+        };
+
+        if transient && attempt < ESI_ATTEMPTS {
+            std::thread::sleep(limited.unwrap_or_default().max(Duration::from_secs(1 << attempt)));
+            attempt += 1;
+            continue;
+        }
+        if let (Some(wait), Ok(_)) = (limited, &result) {
+            std::thread::sleep(wait);
+        }
+        return result.map_err(Box::new);
+    }
+}
+
+/// Fetches a system, returning None when ESI does not know it.
+pub fn get_system(id: u32) -> anyhow::Result<Option<EsiSystem>> {
+    match send(&ureq::get(&format!("{}/universe/systems/{}/", ESI_BASE, id)), None) {
+        Ok(response) => Ok(Some(response.into_json()?)),
+        Err(e) if matches!(*e, ureq::Error::Status(404, _)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn get_constellation(id: u32) -> anyhow::Result<EsiConstellation> {
+    Ok(send(&ureq::get(&format!("{}/universe/constellations/{}/", ESI_BASE, id)), None)?.into_json()?)
+}
+
+/// Resolves IDs to names, ESI takes at most 1000 per request.
+pub fn get_names(ids: &[u32]) -> anyhow::Result<Vec<EsiName>> {
+    ids.chunks(1000)
+        .map(|chunk| Ok(send(&ureq::post(&format!("{}/universe/names/", ESI_BASE)), Some(chunk))?.into_json::<Vec<EsiName>>()?))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map(|names| names.into_iter().flatten().collect())
+}
+
+pub fn get_stargate(id: u32) -> anyhow::Result<EsiStargate> {
+    Ok(send(&ureq::get(&format!("{}/universe/stargates/{}/", ESI_BASE, id)), None)?.into_json()?)
+}
+
+/// The systems a system's stargates lead to.
+pub fn neighbours(system: &EsiSystem) -> anyhow::Result<Vec<u32>> {
+    system.stargates.iter().map(|id| Ok(get_stargate(*id)?.destination.system_id)).collect()
+}
+
+/// Describes each way the map's view of a system differs from ESI.
+pub fn compare_system(id: u32, name: &str, map_neighbours: &[u32]) -> anyhow::Result<Vec<String>> {
+    let Some(system) = get_system(id)? else {
+        return Ok(vec![format!("{} ({}) does not exist in ESI", name, id)]);
+    };
+
+    let mut differences = Vec::new();
+
+    if system.name != name {
+        differences.push(format!("{} ({}) is named {} in ESI", name, id, system.name));
+    }
+
+    let map_neighbours = map_neighbours.iter().copied().collect::<BTreeSet<_>>();
+    let esi_neighbours = neighbours(&system)?.into_iter().collect::<BTreeSet<_>>();
+
+    for removed in map_neighbours.difference(&esi_neighbours) {
+        differences.push(format!("{} ({}) gate to {} is not in ESI", name, id, removed));
+    }
+    for added in esi_neighbours.difference(&map_neighbours) {
+        differences.push(format!("{} ({}) gate to {} is missing from the map", name, id, added));
+    }
+
+    Ok(differences)
+}
+
+/// Where `fetch_map` keeps the cache details for a map file.
+pub fn cache_info_path(map: &str) -> String {
+    format!("{}.esi.json", map)
+}
+
+/// Downloads every system with its stargate neighbours into a map file in the same format as the
+/// hand exported maps. The map is kept until ESI's expiry for the system list, then only fetched
+/// again if the list's etag changed.
+pub fn fetch_map(path: &str, force: bool) -> anyhow::Result<Fetched> {
+    let info_path = cache_info_path(path);
+    let cached = Path::new(path).exists() && !force;
+    let info = match std::fs::read_to_string(&info_path) {
+        Ok(text) if cached => serde_json::from_str::<CacheInfo>(&text).unwrap_or_default(),
+        _ => CacheInfo::default(),
+    };
+
+    let expired = info.expires.as_deref()
+        .and_then(|expires| chrono::DateTime::parse_from_rfc2822(expires).ok())
+        .is_none_or(|expires| expires < chrono::Utc::now());
+    if cached && !expired {
+        return Ok(Fetched::Fresh);
+    }
+
+    let mut request = ureq::get(&format!("{}/universe/systems/", ESI_BASE));
+    if let (true, Some(etag)) = (cached, &info.etag) {
+        request = request.set("If-None-Match", etag);
+    }
+    let response = send(&request, None)?;
+    let fresh = CacheInfo {
+        etag: response.header("ETag").map(str::to_owned),
+        expires: response.header("Expires").map(str::to_owned),
+    };
+    if response.status() == 304 {
+        std::fs::write(&info_path, serde_json::to_string_pretty(&fresh)?)?;
+        return Ok(Fetched::Unchanged);
+    }
+    let ids: Vec<u32> = response.into_json()?;
+
+    // A pool of its own keeps the crawl to ESI_CONCURRENCY requests at a time
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(ESI_CONCURRENCY).build()?;
+    let systems = pool.install(|| ids.par_iter()
+        .filter_map(|id| get_system(*id).transpose())
+        .map(|system| {
+            let mut system = system?;
+            let neighbours = neighbours(&system)?;
+            system.stargates = neighbours;
+            Ok(system)
+        })
+        .collect::<anyhow::Result<Vec<_>>>())?;
+
+    let constellations = systems.iter().map(|s| s.constellation_id).collect::<BTreeSet<_>>();
+    let regions = pool.install(|| constellations.into_par_iter()
+        .map(|id| Ok((id, get_constellation(id)?.region_id)))
+        .collect::<anyhow::Result<HashMap<_, _>>>())?;
+    // Constellation and region IDs never overlap, so one lookup names both
+    let area_ids = regions.iter().flat_map(|(constellation, region)| [*constellation, *region]).collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>();
+    let area_names = get_names(&area_ids)?.into_iter().map(|n| (n.id, n.name)).collect::<HashMap<_, _>>();
+    let station_ids = systems.iter().flat_map(|s| s.stations.iter().copied()).collect::<Vec<_>>();
+    let station_names = get_names(&station_ids)?.into_iter().map(|n| (n.id, n.name)).collect::<HashMap<_, _>>();
+
+    let map = systems.iter().map(|s| (s.system_id.to_string(), json!({
+        "solarSystemID": s.system_id,
+        "solarSystemName": s.name,
+        "regionID": regions[&s.constellation_id],
+        "regionName": area_names.get(&regions[&s.constellation_id]),
+        "constellationID": s.constellation_id,
+        "constellationName": area_names.get(&s.constellation_id),
+        "security": s.security_status,
+        "center": s.position.map(|p| [p.x, p.y, p.z]),
+        "neighbours": s.stargates,
+        "stations": s.stations.iter().map(|id| json!({
+            "stationID": id,
+            "stationName": station_names.get(id).cloned().unwrap_or_default(),
+        })).collect::<Vec<_>>(),
+    }))).collect::<serde_json::Map<_, _>>();
+
+    std::fs::write(path, serde_json::to_string(&map)?)?;
+    std::fs::write(&info_path, serde_json::to_string_pretty(&fresh)?)?;
+    Ok(Fetched::Downloaded(systems.len()))
+}
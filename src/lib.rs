@@ -0,0 +1,4036 @@
+//! Route planning over the EVE Frontier map: building the system graph, finding routes that
+//! cover a region, and the filters and reports around them. The `frontier_route` binary is a
+//! command line front end to this crate, and [`Router`] is the entry point for embedding.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::fmt::Write as _;
+use std::io::{BufReader, BufWriter, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use petgraph::graph::{EdgeIndex, Graph, NodeIndex};
+use petgraph::{Direction, EdgeType, Undirected};
+use petgraph::algo;
+use petgraph::unionfind::UnionFind;
+use petgraph::visit::{Dfs, EdgeRef, GraphBase, GraphRef, IntoNeighbors, IntoNeighborsDirected, IntoNodeReferences, NodeCount, Walker};
+use petgraph::csr::IndexType;
+use petgraph::dot::{Dot, Config};
+use clap::ValueEnum;
+use rayon::prelude::*;
+
+mod esi;
+
+pub use esi::{cache_info_path, Fetched};
+
+/// Why a route couldn't be planned. Broken internal invariants still panic, these are the
+/// failures caused by the input.
+#[derive(Debug)]
+pub enum RouteError {
+    /// A file couldn't be read or written
+    Io { path: String, source: std::io::Error },
+    /// A map, graph cache or other input file isn't in the expected format
+    Parse { path: String, message: String },
+    /// A system name, ID or alias that isn't on the map
+    UnknownSystem(String),
+    /// A system that is on the map but can't be part of the route from the start
+    Disconnected(String),
+    /// Command line values that can't be used together or don't parse
+    InvalidArgument(String),
+    /// A request to ESI failed
+    Fetch(String),
+}
+
+impl RouteError {
+    /// The process exit code for this error. 1 and 2 are kept for --strict and
+    /// --min-unique-ratio, and a panic exits with 101.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RouteError::Io { .. } => 3,
+            RouteError::Parse { .. } => 4,
+            RouteError::UnknownSystem(_) => 5,
+            RouteError::Disconnected(_) => 6,
+            RouteError::InvalidArgument(_) => 7,
+            RouteError::Fetch(_) => 8,
+        }
+    }
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::Io { path, source } => write!(f, "Cannot access {}: {}", path, source),
+            RouteError::Parse { path, message } => write!(f, "Cannot parse {}: {}", path, message),
+            RouteError::UnknownSystem(name) => write!(f, "System {} is not on the map", name),
+            RouteError::Disconnected(message) | RouteError::InvalidArgument(message) => write!(f, "{}", message),
+            RouteError::Fetch(message) => write!(f, "ESI request failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RouteError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a JSON file, reporting a missing file and bad JSON as separate errors.
+fn read_json<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, RouteError> {
+    let file = File::open(path).map_err(|source| RouteError::Io { path: path.to_owned(), source })?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| RouteError::Parse { path: path.to_owned(), message: e.to_string() })
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Cover every system in the start's region
+    #[default]
+    Tour,
+    /// Go from the start to --dest by the cheapest route
+    Shortest,
+    /// Visit every system in the start's region and return with as few jumps as possible,
+    /// rather than stitching together the longest paths
+    Cover,
+    /// Take every connection in the start's region at least once and return, repeating as few
+    /// as possible (the Chinese postman problem)
+    Postman,
+}
+
+/// Route preferences matching the in-game autopilot settings.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// Fewest jumps whatever the security
+    #[default]
+    Shorter,
+    /// Stay in highsec unless there is no other way
+    Safer,
+    /// Stay out of highsec unless there is no other way
+    LessSecure,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CompareMetric {
+    /// Fewest jumps
+    Jumps,
+    /// Highest share of jumps to new systems
+    Efficiency,
+    /// Shortest running time
+    Time,
+}
+
+impl Display for CompareMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompareMetric::Jumps => write!(f, "jumps"),
+            CompareMetric::Efficiency => write!(f, "efficiency"),
+            CompareMetric::Time => write!(f, "time"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RallyMetric {
+    /// Total jumps across all members
+    Sum,
+    /// Jumps for the member furthest away
+    Max,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, ValueEnum)]
+pub enum SecurityClass {
+    /// 0.5 and above, or no security data
+    Highsec,
+    /// Above 0.0 and below 0.5
+    Lowsec,
+    /// 0.0 and below
+    Nullsec,
+}
+
+impl SecurityClass {
+    pub fn of(system: &System) -> SecurityClass {
+        if is_highsec(system) {
+            SecurityClass::Highsec
+        } else if is_nullsec(system) {
+            SecurityClass::Nullsec
+        } else {
+            SecurityClass::Lowsec
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable summary
+    Text,
+    /// Single JSON document with the route, its statistics and a record per jump
+    #[value(alias = "json")]
+    Report,
+    /// One row per jump with the system and connection details, under a header row
+    Csv,
+    /// Comma separated system IDs, can be read back with --route-in
+    Compact,
+    /// The region's neighbour lists as JSON, without routing
+    Adjacency,
+    /// One numbered line per jump naming only the next system, for calling out a route live
+    Breadcrumbs,
+    /// A Dotlan route map URL through every system, to share in a browser
+    Dotlan,
+    /// Markdown task list of the jumps, grouped under a heading per region
+    Checklist,
+    /// One JSON object per jump on its own line, written once the whole route is planned
+    Ndjson,
+    /// Standalone HTML page drawing the region with the route highlighted beside the jump list
+    Html,
+    /// Only the junctions where the pilot has to pick a gate, with the system to head for
+    Turns,
+    /// JSON array of the route's system IDs, without names
+    IdsJson,
+    /// One `From -> To (distance AU)` line per jump, the distance only when both systems have
+    /// coordinates
+    Jumps,
+    /// The systems to set as autopilot destinations one after another, one ID per line, leaving
+    /// out those the autopilot passes through anyway
+    Destinations,
+    /// `system_id<TAB>order` per system in the order it is first visited, for overlay tools
+    Tsv,
+}
+
+/// The map graph every search runs on: systems joined by gates and other connections.
+pub type MapGraph = Graph<System, Connection, Undirected>;
+
+#[allow(non_snake_case, dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct SolarSystem {
+    solarSystemID: u32,
+    solarSystemName: String,
+    regionID: u32,
+    #[serde(default)]
+    regionName: Option<String>,
+    #[serde(default)]
+    constellationID: Option<u32>,
+    #[serde(default)]
+    constellationName: Option<String>,
+    #[serde(default, alias = "securityStatus")]
+    security: Option<f32>,
+    #[serde(default)]
+    center: Option<[f64; 3]>,
+    neighbours: Vec<u32>,
+    stations: Vec<Station>,
+}
+
+#[allow(non_snake_case, dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct Station {
+    stationID: u32,
+    stationName: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct System {
+    pub id: u32,
+    pub name: String,
+    pub region_id: u32,
+    #[serde(default)]
+    pub region_name: Option<String>,
+    #[serde(default)]
+    pub constellation_id: Option<u32>,
+    #[serde(default)]
+    pub constellation_name: Option<String>,
+    #[serde(default)]
+    pub security: Option<f32>,
+    #[serde(default)]
+    pub center: Option<[f64; 3]>,
+    pub has_station: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionKind {
+    Gate,
+    Bridge,
+    Wormhole,
+    /// Added with --link-regions to travel between otherwise separate regions, e.g. by cyno
+    Link,
+}
+
+impl Display for ConnectionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionKind::Gate => write!(f, "gate"),
+            ConnectionKind::Bridge => write!(f, "bridge"),
+            ConnectionKind::Wormhole => write!(f, "wormhole"),
+            ConnectionKind::Link => write!(f, "link"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Connection {
+    pub weight: f32,
+    pub kind: ConnectionKind,
+    /// Estimated travel time, see `TravelTimes`
+    #[serde(default)]
+    pub seconds: f32,
+}
+
+impl Connection {
+    pub fn new(kind: ConnectionKind) -> Connection {
+        Connection { weight: 1.0, kind, seconds: TravelTimes::default().seconds(kind) }
+    }
+}
+
+/// Estimated seconds to cross each kind of connection, including aligning and warping to it.
+#[derive(Debug, Clone, Copy)]
+pub struct TravelTimes {
+    pub gate: f32,
+    pub bridge: f32,
+    pub wormhole: f32,
+}
+
+impl Default for TravelTimes {
+    fn default() -> Self {
+        TravelTimes { gate: 30.0, bridge: 5.0, wormhole: 60.0 }
+    }
+}
+
+impl TravelTimes {
+    pub fn seconds(&self, kind: ConnectionKind) -> f32 {
+        match kind {
+            ConnectionKind::Gate => self.gate,
+            ConnectionKind::Bridge => self.bridge,
+            ConnectionKind::Wormhole => self.wormhole,
+            ConnectionKind::Link => self.bridge,
+        }
+    }
+}
+
+/// Sets every connection's travel time from its kind.
+pub fn apply_travel_times(graph: &mut Graph<System, Connection, Undirected>, times: &TravelTimes) {
+    for connection in graph.edge_weights_mut() {
+        connection.seconds = times.seconds(connection.kind);
+    }
+}
+
+impl Display for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.weight)
+    }
+}
+
+#[derive(Clone)]
+pub struct SystemWithJump {
+    pub id: u32,
+    pub name: String,
+    pub jumps: Vec<usize>,
+}
+
+impl Display for SystemWithJump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:?}", self.name, self.jumps)
+    }
+}
+
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub start: u32,
+    pub strategy: String,
+    pub seed: Option<u64>,
+    pub route: Vec<u32>,
+    pub route_hash: String,
+    pub connections: Vec<ConnectionKind>,
+    pub jumps: usize,
+    pub unique_systems: usize,
+    pub revisits: usize,
+    pub region_transitions: usize,
+    pub constellation_transitions: usize,
+    pub eta_seconds: f64,
+    /// Region systems the route left out, zero for targets routes
+    pub missed_systems: usize,
+    pub region_jumps: BTreeMap<u32, usize>,
+    pub warnings: Vec<String>,
+    pub elapsed_seconds: f64,
+    pub systems_per_second: f64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub routes: BTreeMap<u32, Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    pub steps: Vec<JumpRecord>,
+}
+
+/// One jump of a route with what is known about the system it arrives at.
+#[derive(Debug, Clone, Serialize)]
+pub struct JumpRecord {
+    pub jump: usize,
+    pub id: u32,
+    pub name: String,
+    pub connection: ConnectionKind,
+    pub seconds: f32,
+    pub region_id: u32,
+    pub constellation_id: Option<u32>,
+    pub security: Option<f32>,
+    /// Whether this is the route's first visit to the system
+    pub new_system: bool,
+}
+
+pub fn jump_records(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32]) -> Vec<JumpRecord> {
+    let index = graph.node_references().map(|(i, n)| (n.id, i)).collect::<HashMap<_, _>>();
+    let mut visited = HashSet::from([start_id]);
+    std::iter::once(&start_id).chain(route).collect::<Vec<_>>().windows(2).enumerate().map(|(i, w)| {
+        let (from, to) = (index[w[0]], index[w[1]]);
+        let connection = graph.find_edge(from, to).map(|e| graph[e]).unwrap_or_else(|| Connection::new(ConnectionKind::Gate));
+        let system = &graph[to];
+        JumpRecord {
+            jump: i + 1,
+            id: system.id,
+            name: system.name.clone(),
+            connection: connection.kind,
+            seconds: connection.seconds,
+            region_id: system.region_id,
+            constellation_id: system.constellation_id,
+            security: system.security,
+            new_system: visited.insert(system.id),
+        }
+    }).collect()
+}
+
+pub const CSV_HEADER: &str = "jump,id,name,connection,seconds,region_id,constellation_id,security,new_system";
+
+/// The route as CSV rows under CSV_HEADER, quoting names that need it.
+pub fn csv_rows(records: &[JumpRecord]) -> Vec<String> {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    records.iter().map(|r| {
+        let name = if r.name.contains([',', '"', '\n']) { format!("\"{}\"", r.name.replace('"', "\"\"")) } else { r.name.clone() };
+        format!("{},{},{},{},{},{},{},{},{}", r.jump, r.id, name, r.connection, r.seconds, r.region_id,
+            optional(r.constellation_id.map(|c| c.to_string())), optional(r.security.map(|s| s.to_string())), r.new_system)
+    }).collect()
+}
+
+/// Headline numbers of a route for scheduling a fleet around it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub jumps: usize,
+    pub unique_systems: usize,
+    pub regions: usize,
+    /// Most jumps between rest points: the ends of --leg-size legs, or systems with a station
+    pub longest_leg: usize,
+    pub eta_seconds: f64,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} jumps, {} systems, {} regions, longest leg {} jumps, ETA {}",
+            self.jumps, self.unique_systems, self.regions, self.longest_leg, format_eta(self.eta_seconds))
+    }
+}
+
+pub fn summarize(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32], leg_size: Option<usize>) -> Summary {
+    let systems = graph.node_weights().map(|n| (n.id, n)).collect::<HashMap<_, _>>();
+    let longest_leg = match leg_size {
+        Some(leg_size) => split_legs(graph, route, leg_size).iter().map(|leg| leg.len()).max().unwrap_or_default(),
+        None => route.split_inclusive(|id| systems.get(id).is_some_and(|n| n.has_station)).map(<[u32]>::len).max().unwrap_or_default(),
+    };
+    Summary {
+        jumps: route.len(),
+        unique_systems: unique_systems(route),
+        regions: std::iter::once(&start_id).chain(route).filter_map(|id| systems.get(id)).map(|n| n.region_id).collect::<HashSet<_>>().len(),
+        longest_leg,
+        eta_seconds: route_eta(graph, start_id, route),
+    }
+}
+
+/// The kind of connection used to arrive at each system in the route.
+pub fn route_connections(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32]) -> Vec<ConnectionKind> {
+    std::iter::once(&start_id).chain(route.iter()).collect::<Vec<_>>().windows(2).map(|w| {
+        node_index_of(graph, *w[0]).zip(node_index_of(graph, *w[1]))
+            .and_then(|(a, b)| graph.find_edge(a, b))
+            .map(|e| graph[e].kind)
+            .unwrap_or(ConnectionKind::Gate)
+    }).collect()
+}
+
+/// Estimated seconds to fly the route, summing the travel time of each connection.
+pub fn route_eta(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32]) -> f64 {
+    std::iter::once(&start_id).chain(route.iter()).collect::<Vec<_>>().windows(2).map(|w| {
+        node_index_of(graph, *w[0]).zip(node_index_of(graph, *w[1]))
+            .and_then(|(a, b)| graph.find_edge(a, b))
+            .map_or(0.0, |e| graph[e].seconds as f64)
+    }).sum()
+}
+
+/// Formats seconds as hours, minutes and seconds, leaving out leading zero units.
+pub fn format_eta(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, s) => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+/// BLAKE3 hex digest of the start and route IDs as little-endian u32s, stable across platforms
+/// for comparing routes between builds.
+pub fn route_hash(start_id: u32, route: &[u32]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for id in std::iter::once(&start_id).chain(route) {
+        hasher.update(&id.to_le_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+pub fn unique_systems(route: &[u32]) -> usize {
+    route.iter().collect::<HashSet<_>>().len()
+}
+
+pub fn unique_ratio(route: &[u32]) -> f32 {
+    if route.is_empty() {
+        1.0
+    } else {
+        unique_systems(route) as f32 / route.len() as f32
+    }
+}
+
+/// Unique systems covered per second of search, or zero when the search was instantaneous.
+pub fn throughput(route: &[u32], elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs_f64();
+    if seconds > 0.0 { unique_systems(route) as f64 / seconds } else { 0.0 }
+}
+
+pub fn route_constellation_transitions(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32]) -> usize {
+    let systems = graph.node_weights().map(|n| (n.id, n)).collect::<HashMap<_, _>>();
+    constellation_transitions(std::iter::once(&start_id).chain(route).filter_map(|id| systems.get(id).copied()))
+}
+
+/// Systems in the region the route never reaches, other than the start and exempt systems.
+pub fn missed_systems(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32], exempt: &HashSet<u32>) -> Vec<u32> {
+    let visited = route.iter().collect::<HashSet<_>>();
+    let mut missed = graph.node_weights()
+        .map(|n| n.id)
+        .filter(|id| *id != start_id && !visited.contains(id) && !exempt.contains(id))
+        .collect::<Vec<_>>();
+    missed.sort();
+    missed
+}
+
+pub fn build_report(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32], warnings: &[String]) -> Report {
+    let region_lookup = graph.node_weights().map(|n| (n.id, n.region_id)).collect::<HashMap<_, _>>();
+
+    let unique_systems = unique_systems(route);
+
+    let mut region_jumps = BTreeMap::<u32, usize>::new();
+    for id in route {
+        *region_jumps.entry(region_lookup[id]).or_default() += 1;
+    }
+
+    let region_transitions = std::iter::once(&start_id).chain(route.iter())
+        .map(|id| region_lookup[id])
+        .collect::<Vec<_>>()
+        .windows(2)
+        .filter(|w| w[0] != w[1])
+        .count();
+
+    Report {
+        schema_version: REPORT_SCHEMA_VERSION,
+        start: start_id,
+        strategy: "longest".to_owned(),
+        seed: None,
+        route: route.to_vec(),
+        route_hash: route_hash(start_id, route),
+        connections: route_connections(graph, start_id, route),
+        jumps: route.len(),
+        unique_systems,
+        revisits: revisits(route, start_id),
+        region_transitions,
+        constellation_transitions: route_constellation_transitions(graph, start_id, route),
+        eta_seconds: route_eta(graph, start_id, route),
+        missed_systems: 0,
+        region_jumps,
+        warnings: warnings.to_vec(),
+        elapsed_seconds: 0.0,
+        systems_per_second: 0.0,
+        routes: BTreeMap::new(),
+        summary: None,
+        steps: jump_records(graph, start_id, route),
+    }
+}
+
+const DOT_LOCATIONS: &[&str] = &[
+    "/usr/bin/dot",
+    "/usr/local/bin/dot",
+    "/opt/homebrew/bin/dot",
+    "C:\\Program Files\\Graphviz\\bin\\dot.exe",
+    "C:\\Program Files (x86)\\Graphviz\\bin\\dot.exe",
+];
+
+pub fn find_dot(dot_path: Option<&str>) -> Option<PathBuf> {
+    if let Some(dot_path) = dot_path {
+        return Some(PathBuf::from(dot_path)).filter(|p| p.is_file());
+    }
+
+    let binary = if cfg!(windows) { "dot.exe" } else { "dot" };
+
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).map(|p| p.join(binary)).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .chain(DOT_LOCATIONS.iter().map(PathBuf::from))
+        .find(|p| p.is_file())
+}
+
+pub fn sanitize_file_name(name: &str) -> String {
+    name.chars().map(|c| if "<>:\"/\\|?*".contains(c) { '_' } else { c }).collect()
+}
+
+/// Describes a system with its gate count and, when known, its security status.
+pub fn annotate_system(graph: &Graph<System, Connection, Undirected>, index: NodeIndex) -> String {
+    let system = &graph[index];
+    let gates = match graph.neighbors(index).count() {
+        1 => "1 gate".to_owned(),
+        n => format!("{} gates", n),
+    };
+    match system.security {
+        Some(security) => format!("{} ({}, {:.1})", system.name, gates, security),
+        None => format!("{} ({})", system.name, gates),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DotOptions<'a> {
+    /// Graphviz binary to use instead of searching for one
+    pub dot_path: Option<&'a str>,
+    /// Label edges with their weight to this many decimal places
+    pub edge_label_precision: Option<usize>,
+    /// strftime format for the local time in the file name, no timestamp when unset
+    pub timestamp_format: Option<&'a str>,
+    /// System the route starts from, highlighted in green
+    pub start_id: Option<u32>,
+}
+
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Positions in the route at which each system is visited.
+pub fn jump_positions(path: &[u32]) -> HashMap<u32, Vec<usize>> {
+    let mut jump_positions = HashMap::<u32,Vec<usize>>::new();
+    for (position, id) in path.iter().enumerate() {
+        jump_positions.entry(*id).or_default().push(position);
+    }
+    jump_positions
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Standalone HTML page with an SVG drawing of the region, route connections in red, beside the
+/// numbered list of jumps. Systems are placed by their coordinates seen from above, or around a
+/// circle when any system has none.
+pub fn html_page(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32], names: &HashMap<u32, String>) -> String {
+    const WIDTH: f64 = 900.0;
+    const HEIGHT: f64 = 700.0;
+    const MARGIN: f64 = 60.0;
+
+    let raw = if graph.node_weights().all(|n| n.center.is_some()) {
+        graph.node_weights().map(|n| n.center.map_or((0.0, 0.0), |c| (c[0], -c[2]))).collect::<Vec<_>>()
+    } else {
+        let count = graph.node_count().max(1) as f64;
+        (0..graph.node_count()).map(|i| {
+            let angle = std::f64::consts::TAU * i as f64 / count;
+            (angle.cos(), angle.sin())
+        }).collect()
+    };
+    let (min_x, max_x) = raw.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (x, _)| (lo.min(*x), hi.max(*x)));
+    let (min_y, max_y) = raw.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (_, y)| (lo.min(*y), hi.max(*y)));
+    let scale = ((WIDTH - 2.0 * MARGIN) / (max_x - min_x).max(f64::EPSILON)).min((HEIGHT - 2.0 * MARGIN) / (max_y - min_y).max(f64::EPSILON));
+    let points = raw.iter().map(|(x, y)| (MARGIN + (x - min_x) * scale, MARGIN + (y - min_y) * scale)).collect::<Vec<_>>();
+
+    let full_route = std::iter::once(start_id).chain(route.iter().copied()).collect::<Vec<_>>();
+    let route_edges = full_route.windows(2).map(|w| sort_tuple((w[0], w[1]))).collect::<HashSet<_>>();
+    let jumps = jump_positions(route);
+    let end_id = route.last().copied();
+
+    let mut svg = String::new();
+    for e in graph.edge_references() {
+        let (a, b) = (e.source(), e.target());
+        let class = if route_edges.contains(&sort_tuple((graph[a].id, graph[b].id))) { "route" } else { "gate" };
+        let _ = writeln!(svg, r#"<line class="{}" x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}"/>"#,
+            class, points[a.index()].0, points[a.index()].1, points[b.index()].0, points[b.index()].1);
+    }
+    for (i, n) in graph.node_references() {
+        let class = match (n.id == start_id, Some(n.id) == end_id, jumps.contains_key(&n.id)) {
+            (true, _, _) => "start",
+            (_, true, _) => "end",
+            (_, _, true) => "visited",
+            _ => "missed",
+        };
+        let label = SystemWithJump { id: n.id, name: names.get(&n.id).cloned().unwrap_or_else(|| n.name.clone()), jumps: jumps.get(&n.id).cloned().unwrap_or_default() };
+        let (x, y) = points[i.index()];
+        let _ = writeln!(svg, r#"<circle class="{}" cx="{:.1}" cy="{:.1}" r="6"><title>{}</title></circle>"#, class, x, y, escape_html(&label.to_string()));
+        let _ = writeln!(svg, r#"<text x="{:.1}" y="{:.1}">{}</text>"#, x + 8.0, y - 8.0, escape_html(&label.name));
+    }
+
+    let steps = full_route.iter().enumerate()
+        .map(|(i, id)| format!("<li value=\"{}\">{}</li>", i, escape_html(names.get(id).map_or("?", String::as_str))))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let title = escape_html(names.get(&start_id).map_or("?", String::as_str));
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Route from {title}</title>
+<style>
+body {{ font-family: sans-serif; display: flex; gap: 2em; margin: 1em; }}
+svg {{ border: 1px solid #ccc; flex: none; }}
+line.gate {{ stroke: #bbb; stroke-width: 1; }}
+line.route {{ stroke: #d33; stroke-width: 3; }}
+circle {{ stroke: #333; fill: #4682b4; }}
+circle.start {{ fill: #2c2; r: 9; }}
+circle.end {{ fill: #d33; }}
+circle.missed {{ fill: #ddd; }}
+text {{ font-size: 11px; }}
+ol {{ columns: 2; }}
+</style>
+</head>
+<body>
+<svg width="{width}" height="{height}" viewBox="0 0 {width} {height}">
+{svg}</svg>
+<div>
+<h1>Route from {title}</h1>
+<p>{jumps} jumps, {systems} systems</p>
+<ol start="0">
+{steps}
+</ol>
+</div>
+</body>
+</html>
+"#, title = title, width = WIDTH, height = HEIGHT, svg = svg, jumps = route.len(), systems = unique_systems(&full_route), steps = steps)
+}
+
+pub fn debug_graph(graph: &Graph<System, Connection, Undirected>, path: &[u32], name: String, options: &DotOptions, warnings: &mut Vec<String>) {
+    let name = sanitize_file_name(&name);
+    let dot_file = match options.timestamp_format {
+        Some(format) => {
+            let mut timestamp = String::new();
+            if write!(timestamp, "{}", chrono::Local::now().format(format)).is_err() {
+                warnings.push(format!("Invalid --timestamp-format {:?}, using {}", format, DEFAULT_TIMESTAMP_FORMAT));
+                timestamp = chrono::Local::now().format(DEFAULT_TIMESTAMP_FORMAT).to_string();
+            }
+            format!("graph_{}_{}.dot", name, sanitize_file_name(&timestamp))
+        }
+        None => format!("graph_{}.dot", name),
+    };
+
+    let jump_positions = jump_positions(path);
+
+    let graph_with_jumps = graph.filter_map(|_, s| {
+        Some(SystemWithJump {
+            id: s.id,
+            name: s.name.clone(),
+            jumps: jump_positions.get(&s.id).cloned().unwrap_or_default(),
+        })
+    }, |_, d| Some(format!("{:.*}", options.edge_label_precision.unwrap_or_default(), d.weight)));
+
+    let config: &[Config] = if options.edge_label_precision.is_some() { &[] } else { &[Config::EdgeNoLabel] };
+
+    // The start is a green double circle and the last system a red octagon, a route ending back
+    // at the start gets both as a red outline
+    let end_id = path.last().copied();
+    let node_attributes = |_, (_, system): (NodeIndex, &SystemWithJump)| {
+        match (Some(system.id) == options.start_id, Some(system.id) == end_id) {
+            (true, true) => "shape=doublecircle, style=filled, fillcolor=green, color=red, penwidth=3".to_owned(),
+            (true, false) => "shape=doublecircle, style=filled, fillcolor=green".to_owned(),
+            (false, true) => "shape=octagon, style=filled, fillcolor=red".to_owned(),
+            (false, false) => String::new(),
+        }
+    };
+
+    let output = format!("{}", Dot::with_attr_getters(&graph_with_jumps, config, &|_, _| String::new(), &node_attributes));
+    if let Err(e) = File::create(&dot_file).and_then(|mut f| f.write_all(output.as_bytes())) {
+        warnings.push(format!("Cannot write {}: {}", dot_file, e));
+        return;
+    }
+
+    let Some(dot) = find_dot(options.dot_path) else {
+        warnings.push(match options.dot_path {
+            Some(path) => format!("Graphviz dot not found at {}, {} was not rendered", path, dot_file),
+            None => format!("Graphviz dot not found on PATH or in the usual install locations, {} was not rendered (use --dot-path)", dot_file),
+        });
+        return;
+    };
+
+    let png_file = Path::new(&dot_file).with_extension("png");
+    match Command::new(&dot).arg("-Tpng").arg("-o").arg(&png_file).arg(&dot_file).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => warnings.push(format!("{} failed to render {}: {}", dot.display(), dot_file, status)),
+        Err(e) => warnings.push(format!("{} failed to render {}: {}", dot.display(), dot_file, e)),
+    }
+}
+
+pub fn filter_nodes<'a, N, E, Ty, Ix, F>(
+    graph: &'a Graph<N, E, Ty, Ix>,
+    mut node_map: F,
+) -> Graph<N, E, Ty, Ix>
+where
+    F: FnMut(NodeIndex<Ix>, &'a N) -> bool,
+    Ty: EdgeType,
+    Ix: IndexType,
+    N: Clone,
+    E: Clone,
+{
+    graph.filter_map(|i, n| {
+        if node_map(i, n) {
+            Some(n.clone())
+        } else {
+            None
+        }
+    }, |_, e| Some(e.clone()))
+}
+
+pub fn within_radius(graph: &Graph<System, Connection, Undirected>, start_index: NodeIndex, radius: usize) -> Graph<System, Connection, Undirected> {
+    let distances = algo::dijkstra(graph, start_index, None, |_| 1usize);
+    filter_nodes(graph, |i, _| distances.get(&i).is_some_and(|d| *d <= radius))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedGraph {
+    systems: Vec<System>,
+    edges: Vec<(u32, u32, Connection)>,
+}
+
+pub fn save_graph(graph: &Graph<System, Connection, Undirected>, path: &str) -> Result<(), RouteError> {
+    let cached = CachedGraph {
+        systems: graph.node_weights().cloned().collect(),
+        edges: graph.edge_references().map(|e| (graph[e.source()].id, graph[e.target()].id, *e.weight())).collect(),
+    };
+
+    let file = File::create(path).map_err(|source| RouteError::Io { path: path.to_owned(), source })?;
+    serde_json::to_writer(BufWriter::new(file), &cached).map_err(|e| RouteError::Io { path: path.to_owned(), source: e.into() })
+}
+
+pub fn load_graph(path: &str) -> Result<Graph<System, Connection, Undirected>, RouteError> {
+    let cached: CachedGraph = read_json(path)?;
+
+    let mut graph = Graph::<System, Connection, Undirected>::new_undirected();
+    let node_index = cached.systems.into_iter()
+        .map(|s| (s.id, graph.add_node(s)))
+        .collect::<HashMap<_, _>>();
+
+    for (a, b, weight) in cached.edges {
+        let (Some(a), Some(b)) = (node_index.get(&a), node_index.get(&b)) else {
+            return Err(RouteError::Parse { path: path.to_owned(), message: format!("edge {} - {} joins a system that isn't cached", a, b) });
+        };
+        graph.add_edge(*a, *b, weight);
+    }
+
+    Ok(graph)
+}
+
+#[derive(Debug, Default)]
+pub struct BuildOptions {
+    pub check_symmetry: bool,
+    pub weights: HashMap<(u32, u32), f32>,
+}
+
+/// Loads edge weight overrides, keyed by the sorted pair of system IDs.
+pub fn load_weights(path: &str) -> Result<HashMap<(u32, u32), f32>, RouteError> {
+    let entries: Vec<(u32, u32, f32)> = read_json(path)?;
+    for (a, b, weight) in &entries {
+        if !weight.is_finite() || *weight < 0.0 {
+            return Err(RouteError::Parse { path: path.to_owned(), message: format!("weight {} for {} - {} must be a non-negative number", weight, a, b) });
+        }
+    }
+    Ok(entries.into_iter().map(|(a, b, weight)| (sort_tuple((a, b)), weight)).collect())
+}
+
+pub fn build_graph(data: &HashMap<u32, SolarSystem>, options: &BuildOptions, warnings: &mut Vec<String>) -> Graph<System, Connection, Undirected> {
+    let mut graph = Graph::<System, Connection, Undirected>::new_undirected();
+    let mut node_index = HashMap::<u32, NodeIndex>::new();
+
+    let mut entries = data.iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(key, _)| **key);
+
+    let mut systems = Vec::<&SolarSystem>::new();
+    for (key, ss) in entries {
+        if node_index.contains_key(&ss.solarSystemID) {
+            warnings.push(format!("{} ({}) under key {} duplicates an earlier system, ignoring it", ss.solarSystemName, ss.solarSystemID, key));
+            continue;
+        }
+        node_index.insert(ss.solarSystemID, graph.add_node(System {
+            id: ss.solarSystemID,
+            name: ss.solarSystemName.clone(),
+            region_id: ss.regionID,
+            region_name: ss.regionName.clone(),
+            constellation_id: ss.constellationID,
+            constellation_name: ss.constellationName.clone(),
+            security: ss.security,
+            center: ss.center,
+            has_station: !ss.stations.is_empty(),
+        }));
+        systems.push(ss);
+    }
+
+    let neighbour_lookup = systems.iter()
+        .map(|ss| (ss.solarSystemID, &ss.neighbours))
+        .collect::<HashMap<_, _>>();
+
+    let mut added = HashSet::<(u32,u32)>::new();
+    for ss in systems {
+        let index1 = *node_index.get(&ss.solarSystemID).unwrap();
+        let mut listed = HashSet::<u32>::new();
+        for n in &ss.neighbours {
+            if !listed.insert(*n) {
+                warnings.push(format!("{} lists neighbour {} more than once", ss.solarSystemName, n));
+                continue;
+            }
+            let Some(index2) = node_index.get(n).copied() else {
+                warnings.push(format!("{} lists missing neighbour {}", ss.solarSystemName, n));
+                continue;
+            };
+            if *n == ss.solarSystemID {
+                warnings.push(format!("{} lists itself as a neighbour", ss.solarSystemName));
+                continue;
+            }
+            if options.check_symmetry && !neighbour_lookup[n].contains(&ss.solarSystemID) {
+                warnings.push(format!("{} lists {} as a neighbour but not the reverse", ss.solarSystemName, n));
+            }
+            let system_pair = sort_tuple((ss.solarSystemID, *n));
+            if !added.contains(&system_pair) {
+                graph.add_edge(index1, index2, Connection::new(ConnectionKind::Gate));
+                added.insert(system_pair);
+            }
+        }
+    }
+
+    apply_weights(&mut graph, &options.weights, warnings);
+    graph
+}
+
+/// Overrides the weight of each listed connection, warning about pairs that aren't connected.
+pub fn apply_weights(graph: &mut Graph<System, Connection, Undirected>, weights: &HashMap<(u32, u32), f32>, warnings: &mut Vec<String>) {
+    let mut pairs = weights.iter().collect::<Vec<_>>();
+    pairs.sort_by_key(|(pair, _)| **pair);
+    for ((a, b), weight) in pairs {
+        let edge = match (node_index_of(graph, *a), node_index_of(graph, *b)) {
+            (Some(a), Some(b)) => graph.find_edge(a, b),
+            _ => None,
+        };
+        match edge {
+            Some(edge) => graph[edge].weight = *weight,
+            None => warnings.push(format!("Weight given for {} - {} but they are not connected", a, b)),
+        }
+    }
+}
+
+/// Adds a unit-weight connection between two systems, returning false if either is missing or
+/// they are already connected.
+pub fn add_connection(graph: &mut Graph<System, Connection, Undirected>, a: u32, b: u32, kind: ConnectionKind) -> bool {
+    match (node_index_of(graph, a), node_index_of(graph, b)) {
+        (Some(a), Some(b)) if graph.find_edge(a, b).is_none() => {
+            graph.add_edge(a, b, Connection::new(kind));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Removes the connection between two systems, returning false if there was none.
+pub fn remove_connection(graph: &mut Graph<System, Connection, Undirected>, a: u32, b: u32) -> bool {
+    let edge = match (node_index_of(graph, a), node_index_of(graph, b)) {
+        (Some(a), Some(b)) => graph.find_edge(a, b),
+        _ => None,
+    };
+
+    edge.and_then(|e| graph.remove_edge(e)).is_some()
+}
+
+/// Finds a system by exact name, or by ID when given a number.
+pub fn find_system<E>(graph: &Graph<System, E, Undirected>, name_or_id: &str) -> Option<NodeIndex> {
+    let id = name_or_id.parse::<u32>().ok();
+    graph.node_references().find(|(_, system)| {
+        system.name == name_or_id || Some(system.id) == id
+    }).map(|(i, _)| i)
+}
+
+/// Finds a region's ID by name, or by ID when no region has that name. Maps without region names
+/// can only be searched by ID.
+pub fn find_region<E>(graph: &Graph<System, E, Undirected>, name_or_id: &str) -> Option<u32> {
+    graph.node_weights().find(|system| system.region_name.as_deref() == Some(name_or_id)).map(|system| system.region_id)
+        .or_else(|| name_or_id.parse().ok().filter(|id| graph.node_weights().any(|system| system.region_id == *id)))
+}
+
+/// Finds a constellation's ID by name, or by ID when no constellation has that name, as
+/// `find_region` does for regions.
+pub fn find_constellation<E>(graph: &Graph<System, E, Undirected>, name_or_id: &str) -> Option<u32> {
+    graph.node_weights().find(|system| system.constellation_name.as_deref() == Some(name_or_id)).and_then(|system| system.constellation_id)
+        .or_else(|| name_or_id.parse().ok().filter(|id| graph.node_weights().any(|system| system.constellation_id == Some(*id))))
+}
+
+/// Like `find_system`, for systems that have to be on the map.
+pub fn require_system<E>(graph: &Graph<System, E, Undirected>, name_or_id: &str) -> Result<NodeIndex, RouteError> {
+    find_system(graph, name_or_id).ok_or_else(|| RouteError::UnknownSystem(name_or_id.to_owned()))
+}
+
+/// Plans a covering route over the start system's region, choosing each outbound segment with
+/// the given scorer instead of by length.
+pub fn plan_route<F>(graph: &Graph<System, Connection, Undirected>, start_id: u32, scorer: F) -> Vec<u32>
+where
+    F: Fn(&[NodeIndex], &Graph<System, Connection, Undirected>) -> i64 + Send + Sync + 'static,
+{
+    let options = SearchOptions {
+        scorer: Some(Arc::new(scorer)),
+        ..Default::default()
+    };
+    find_longest_paths(region_of(graph, start_id), start_id, &options)
+}
+
+/// Holds the built map graph so routes can be planned again and again with different systems
+/// avoided, without reloading the map. Each plan only filters the graph and searches.
+///
+/// ```no_run
+/// use frontier_route::Router;
+/// use std::collections::HashSet;
+///
+/// let planner = Router::from_map("map.json", &mut Vec::new())?
+///     .max_path_len(30)
+///     .transit_only([30000142]);
+/// let route = planner.plan(30000001, &HashSet::new())?;
+/// # Ok::<(), frontier_route::RouteError>(())
+/// ```
+pub struct Router {
+    graph: MapGraph,
+    options: SearchOptions,
+}
+
+impl Router {
+    pub fn new(graph: MapGraph) -> Router {
+        Router { graph, options: SearchOptions::default() }
+    }
+
+    /// Loads and builds a map in the standard schema, adding any problems found to `warnings`.
+    pub fn from_map(path: &str, warnings: &mut Vec<String>) -> Result<Router, RouteError> {
+        let data = load_map(path, &FieldNames::default())?;
+        Ok(Router::new(build_graph(&data, &BuildOptions::default(), warnings)))
+    }
+
+    /// Replaces the search options used by every plan.
+    pub fn options(mut self, options: SearchOptions) -> Router {
+        self.options = options;
+        self
+    }
+
+    /// Caps the intermediate systems in each candidate path, see `--max-path-len`.
+    pub fn max_path_len(mut self, len: usize) -> Router {
+        self.options.max_path_len = Some(len);
+        self
+    }
+
+    /// Systems passed through on the way but never worth a detour on their own.
+    pub fn transit_only(mut self, ids: impl IntoIterator<Item = u32>) -> Router {
+        self.options.transit_only.extend(ids);
+        self
+    }
+
+    pub fn graph(&self) -> &MapGraph {
+        &self.graph
+    }
+
+    /// The start system's region once the avoided systems are removed.
+    pub fn region(&self, start_id: u32, avoid: &HashSet<u32>) -> Result<MapGraph, RouteError> {
+        if node_index_of(&self.graph, start_id).is_none() {
+            return Err(RouteError::UnknownSystem(start_id.to_string()));
+        }
+        if avoid.contains(&start_id) {
+            return Err(RouteError::Disconnected(format!("Starting system {} is avoided", start_id)));
+        }
+        if avoid.is_empty() {
+            return Ok(region_of(&self.graph, start_id));
+        }
+        Ok(region_of(&filter_nodes(&self.graph, |_, n| !avoid.contains(&n.id)), start_id))
+    }
+
+    /// Plans a covering route over the start system's region without the avoided systems.
+    pub fn plan(&self, start_id: u32, avoid: &HashSet<u32>) -> Result<Vec<u32>, RouteError> {
+        Ok(find_longest_paths(self.region(start_id, avoid)?, start_id, &self.options))
+    }
+
+    /// Plans whatever `request` asks for over the start system's region without the avoided
+    /// systems, see [`plan_request`].
+    pub fn route(&self, start_id: u32, avoid: &HashSet<u32>, request: &RouteRequest, warnings: &mut Vec<String>) -> Result<PlannedRoute, RouteError> {
+        plan_request(&self.region(start_id, avoid)?, start_id, request, &self.options, warnings)
+    }
+}
+
+/// What to plan from a start system. Systems are given by ID, already found in the region.
+#[derive(Clone, Debug, Default)]
+pub struct RouteRequest {
+    pub mode: Mode,
+    /// Where `Mode::Shortest` goes
+    pub dest: Option<u32>,
+    /// Finish here instead of returning to the start
+    pub end: Option<u32>,
+    /// Only visit these systems, in the cheapest order
+    pub targets: Vec<u32>,
+    /// Pairs of targets where the first must be visited before the second
+    pub before: Vec<(u32, u32)>,
+    /// Return to the start after the last target
+    pub round_trip: bool,
+    /// A finished route to use as it is instead of planning one
+    pub route: Option<Vec<u32>>,
+    /// A partial route to continue
+    pub resume: Option<Vec<u32>>,
+    /// Systems (names or IDs) the route must begin with
+    pub prefix: Vec<String>,
+    /// Retry a timed out search with a smaller `max_path_len` until it finishes within this
+    pub retry_within: Option<Duration>,
+    /// In tree regions, finish at the furthest system instead of walking back to the start
+    pub no_return_on_leaf_only: bool,
+    /// Finish wherever the last new system is reached
+    pub one_way: bool,
+    /// Passes of `optimize_route` to run over the finished route
+    pub optimize: Option<usize>,
+}
+
+/// A route planned by [`plan_request`], with what the chosen mode found along the way.
+#[derive(Clone, Debug, Default)]
+pub struct PlannedRoute {
+    pub route: Vec<u32>,
+    /// The strategy used, none for a given route
+    pub strategy: Option<&'static str>,
+    /// The final return to the start was dropped from a tree region
+    pub return_suppressed: bool,
+    /// Jumps repeating a connection, for `Mode::Postman`
+    pub repeated: Option<usize>,
+    /// Jumps saved and stretches replaced by `optimize_route`
+    pub optimized: Option<(i64, usize)>,
+}
+
+/// Plans a route from the start over `graph`, which should already be the start's region, by
+/// the mode and options in `request`. Anything worth telling the user that doesn't stop the
+/// route is added to `warnings`.
+pub fn plan_request(graph: &Graph<System, Connection, Undirected>, start_id: u32, request: &RouteRequest, options: &SearchOptions, warnings: &mut Vec<String>) -> Result<PlannedRoute, RouteError> {
+    let start_index = node_index_of(graph, start_id).ok_or_else(|| RouteError::UnknownSystem(start_id.to_string()))?;
+    let mut planned = PlannedRoute {
+        return_suppressed: request.no_return_on_leaf_only && request.route.is_none() && !algo::is_cyclic_undirected(graph),
+        ..Default::default()
+    };
+
+    if let Some(end) = request.end {
+        node_index_of(graph, end).ok_or_else(|| RouteError::Disconnected(format!("End system {} is not in the region", end)))?;
+    }
+
+    let route = if let Some(route) = &request.route {
+        if let Some(id) = route.iter().find(|id| node_index_of(graph, **id).is_none()) {
+            return Err(RouteError::Disconnected(format!("Route system {} is not in the region", id)));
+        }
+        route.clone()
+    } else if request.mode == Mode::Shortest {
+        planned.strategy = Some("shortest");
+        let dest = request.dest.ok_or_else(|| RouteError::InvalidArgument("Shortest mode needs a destination".to_owned()))?;
+        let dest_index = node_index_of(graph, dest)
+            .ok_or_else(|| RouteError::Disconnected(format!("{} can't be reached from {}", dest, start_id)))?;
+        shortest_route(graph, start_index, dest_index).expect("Region is connected")
+    } else if !request.targets.is_empty() {
+        planned.strategy = Some("targets");
+        match request.end {
+            // The end is one more target, after all the others
+            Some(end) if end != start_id => {
+                let mut targets = request.targets.clone();
+                targets.retain(|id| *id != end);
+                let mut before = request.before.clone();
+                before.extend(targets.iter().map(|id| (*id, end)));
+                targets.push(end);
+                visit_targets(graph, start_id, &targets, &before, false)
+            }
+            end => visit_targets(graph, start_id, &request.targets, &request.before, request.round_trip || end.is_some()),
+        }
+    } else if request.mode == Mode::Cover {
+        planned.strategy = Some("cover");
+        cover_route(graph, start_id, &options.transit_only, request.end)
+    } else if request.mode == Mode::Postman {
+        planned.strategy = Some("postman");
+        let (route, repeated) = postman_route(graph, start_id, request.end);
+        planned.repeated = Some(repeated);
+        route
+    } else {
+        planned.strategy = Some("longest");
+        let previous = match &request.resume {
+            Some(previous) => Some(previous.clone()),
+            None if !request.prefix.is_empty() => Some(pinned_prefix(graph, start_id, &request.prefix)?),
+            None => None,
+        };
+
+        let result = match (previous, request.retry_within) {
+            (None, Some(timeout)) => {
+                let (route, cap) = find_longest_paths_with_retry(graph, start_id, options, timeout);
+                if cap != options.max_path_len {
+                    warnings.push(format!("Search completed with --max-path-len {}", cap.unwrap_or_default()));
+                }
+                route
+            }
+            (previous, _) => {
+                let route = match previous {
+                    Some(previous) => resume_route(graph, previous, start_id, options)?,
+                    None => find_longest_paths(graph.clone(), start_id, options),
+                };
+                if options.timed_out() {
+                    warnings.push("Search timed out, the route is partial".to_owned());
+                }
+                route
+            }
+        };
+
+        match request.end {
+            Some(end) => end_route_at(graph, start_id, &result, end),
+            None if planned.return_suppressed => drop_tree_return(&result, start_id),
+            None => result,
+        }
+    };
+
+    let route = if request.one_way && request.mode != Mode::Postman { drop_tree_return(&route, start_id) } else { route };
+
+    planned.route = match request.optimize {
+        Some(passes) => {
+            let (optimized, replaced) = optimize_route(graph, start_id, &route, passes);
+            planned.optimized = Some((route.len() as i64 - optimized.len() as i64, replaced));
+            optimized
+        }
+        None => route,
+    };
+    Ok(planned)
+}
+
+/// Splits the start system's region between pilots who all leave from the start. The region is
+/// cut along a BFS tree from the start: branches bigger than a fair share are broken into their
+/// sub-branches, then handed out largest first to whichever pilot has the least so far. Each
+/// returned set holds a pilot's systems, including the start and any systems they pass through
+/// to reach their branches.
+pub fn split_between_pilots(graph: &Graph<System, Connection, Undirected>, start_id: u32, pilots: usize) -> Vec<HashSet<u32>> {
+    let start_index = node_index_of(graph, start_id).expect("Start node disappeared");
+    let mut children = HashMap::<NodeIndex, Vec<NodeIndex>>::new();
+    let mut order = vec![start_index];
+    let mut seen = HashSet::from([start_index]);
+    let mut queue = VecDeque::from([start_index]);
+    while let Some(node) = queue.pop_front() {
+        for next in graph.neighbors(node) {
+            if seen.insert(next) {
+                children.entry(node).or_default().push(next);
+                order.push(next);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    let mut size = HashMap::<NodeIndex, usize>::new();
+    for node in order.iter().rev() {
+        let below = children.get(node).map_or(0, |c| c.iter().map(|c| size[c]).sum());
+        size.insert(*node, below + 1);
+    }
+
+    let share = (order.len() - 1).div_ceil(pilots.max(1));
+    let mut branches = children.get(&start_index).cloned().unwrap_or_default().into_iter()
+        .map(|root| (Vec::new(), root))
+        .collect::<Vec<(Vec<NodeIndex>, NodeIndex)>>();
+    while branches.len() < order.len() {
+        let (i, _) = match branches.iter().enumerate().max_by_key(|(_, (_, root))| size[root]) {
+            Some(largest) => largest,
+            None => break,
+        };
+        let root = branches[i].1;
+        if size[&root] <= share || !children.contains_key(&root) {
+            break;
+        }
+        let (mut via, root) = branches.swap_remove(i);
+        via.push(root);
+        branches.extend(children[&root].iter().map(|child| (via.clone(), *child)));
+    }
+    branches.sort_by_key(|(_, root)| std::cmp::Reverse(size[root]));
+
+    let mut shares = vec![(0, HashSet::from([start_id])); pilots.max(1)];
+    for (via, root) in branches {
+        let (load, systems) = shares.iter_mut().min_by_key(|(load, _)| *load).expect("No pilots");
+        *load += size[&root];
+        systems.extend(via.iter().map(|n| graph[*n].id));
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            systems.insert(graph[node].id);
+            stack.extend(children.get(&node).into_iter().flatten());
+        }
+    }
+    shares.into_iter().map(|(_, systems)| systems).collect()
+}
+
+/// Repairs `previous` after the connection between `changed` systems was added to or removed from
+/// the graph, restricted to the start system's region. The route is kept up to the first jump
+/// over a removed connection, or up to the first visit to either end of an added one, and only
+/// the rest is searched again with `options` before returning to wherever `previous` finished.
+/// A route the change doesn't touch is returned unchanged.
+pub fn reroute(graph: &Graph<System, Connection, Undirected>, start_id: u32, previous: &[u32], changed: (u32, u32), options: &SearchOptions) -> Result<Vec<u32>, RouteError> {
+    let region = region_of(graph, start_id);
+    let changed = sort_tuple(changed);
+    let added = match (node_index_of(&region, changed.0), node_index_of(&region, changed.1)) {
+        (Some(a), Some(b)) => region.find_edge(a, b).is_some(),
+        _ => false,
+    };
+
+    let route = std::iter::once(start_id).chain(previous.iter().copied()).collect::<Vec<_>>();
+    let kept = if added {
+        route.iter().position(|id| *id == changed.0 || *id == changed.1).map(|i| i + 1)
+    } else {
+        route.windows(2).position(|w| sort_tuple((w[0], w[1])) == changed).map(|i| i + 1)
+    };
+    let Some(kept) = kept else {
+        return Ok(previous.to_vec());
+    };
+
+    let mut rerouted = resume_route(&region, route[1..kept].to_vec(), start_id, options)?;
+    let finish = previous.last().copied().unwrap_or(start_id);
+    let last = rerouted.last().copied().unwrap_or(start_id);
+    if last != finish {
+        if let Some(finish) = node_index_of(&region, finish) {
+            let back = shortest_route(&region, node_index_of(&region, last).expect("Route node disappeared"), finish)
+                .expect("Region is connected");
+            rerouted.extend(back);
+        }
+    }
+    Ok(rerouted)
+}
+
+/// The `limit` shortest simple paths from one system to another of at most `max_len` jumps, in
+/// order, and how many such paths there are in total.
+pub fn shortest_simple_paths(graph: &Graph<System, Connection, Undirected>, from: NodeIndex, to: NodeIndex, max_len: usize, limit: usize) -> (Vec<Vec<NodeIndex>>, usize) {
+    if from == to || max_len == 0 {
+        return (Vec::new(), 0);
+    }
+
+    // Keep only the best `limit` paths while enumerating, the longest on top to be evicted
+    let mut best = std::collections::BinaryHeap::<(usize, Vec<NodeIndex>)>::new();
+    let mut total = 0;
+    for path in algo::all_simple_paths::<Vec<_>, _>(graph, from, to, 0, Some(max_len - 1)) {
+        total += 1;
+        best.push((path.len(), path));
+        if best.len() > limit {
+            best.pop();
+        }
+    }
+    (best.into_sorted_vec().into_iter().map(|(_, path)| path).collect(), total)
+}
+
+/// The cheapest route between two systems by connection weight, as the systems after `from`, or
+/// None if `to` can't be reached.
+pub fn shortest_route(graph: &Graph<System, Connection, Undirected>, from: NodeIndex, to: NodeIndex) -> Option<Vec<u32>> {
+    let heuristic = Heuristic::new(graph, to);
+    algo::astar(graph, from, |n| n == to, |e| e.weight().weight, |n| heuristic.estimate(&graph[n]))
+        .map(|(_, path)| path.into_iter().skip(1).map(|n| graph[n].id).collect())
+}
+
+/// Returns the connected component containing the given system, i.e. every system reachable from
+/// it by any number of jumps. Panics if the system is not in the graph.
+pub fn region_of(graph: &Graph<System, Connection, Undirected>, start_id: u32) -> Graph<System, Connection, Undirected> {
+    let start_index = node_index_of(graph, start_id).expect("Start node disappeared");
+    let nodes = Dfs::new(graph, start_index).iter(graph).collect::<HashSet<_>>();
+    filter_nodes(graph, |i, _| nodes.contains(&i))
+}
+
+/// Systems without security data count as highsec.
+pub fn is_highsec(system: &System) -> bool {
+    system.security.is_none_or(|security| security >= 0.5)
+}
+
+/// Adds a penalty to the weight of every connection into a system below 0.5 security.
+pub fn apply_highsec_penalty(graph: &mut Graph<System, Connection, Undirected>, penalty: f32) {
+    for e in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(e).expect("Edge disappeared");
+        if !is_highsec(&graph[a]) || !is_highsec(&graph[b]) {
+            graph[e].weight += penalty;
+        }
+    }
+}
+
+/// Systems at or below 0.0 security, never true without security data.
+pub fn is_nullsec(system: &System) -> bool {
+    system.security.is_some_and(|security| security <= 0.0)
+}
+
+/// Adds a penalty to the weight of every gate crossing straight between highsec and nullsec,
+/// where border camps sit. Unlike --prefer-highsec, lowsec systems themselves cost nothing extra.
+pub fn apply_border_penalty(graph: &mut Graph<System, Connection, Undirected>, penalty: f32) {
+    for e in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(e).expect("Edge disappeared");
+        if (is_highsec(&graph[a]) && is_nullsec(&graph[b])) || (is_nullsec(&graph[a]) && is_highsec(&graph[b])) {
+            graph[e].weight += penalty;
+        }
+    }
+}
+
+/// Jumps added to each connection a profile avoids, enough that any detour of fewer jumps wins.
+pub const PROFILE_PENALTY: f32 = 50.0;
+
+/// Weights connections for a routing profile. Safer penalises every connection touching a system
+/// below 0.5 security, less-secure every connection touching a known highsec system.
+pub fn apply_profile(graph: &mut Graph<System, Connection, Undirected>, profile: Profile) {
+    let avoided = |system: &System| match profile {
+        Profile::Shorter => false,
+        Profile::Safer => !is_highsec(system),
+        Profile::LessSecure => system.security.is_some_and(|security| security >= 0.5),
+    };
+    for e in graph.edge_indices() {
+        let (a, b) = graph.edge_endpoints(e).expect("Edge disappeared");
+        if avoided(&graph[a]) || avoided(&graph[b]) {
+            graph[e].weight += PROFILE_PENALTY;
+        }
+    }
+}
+
+pub fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt()
+}
+
+/// Box-drawing tree of the route: each system hangs under the system the route first reached it
+/// from, labelled with that jump number, branches in the order the route takes them.
+pub fn route_tree(start_id: u32, route: &[u32], names: &HashMap<u32, String>) -> Vec<String> {
+    let mut children = HashMap::<u32, Vec<(u32, usize)>>::new();
+    let mut seen = HashSet::from([start_id]);
+    let mut previous = start_id;
+    for (i, id) in route.iter().enumerate() {
+        if seen.insert(*id) {
+            children.entry(previous).or_default().push((*id, i + 1));
+        }
+        previous = *id;
+    }
+
+    fn draw(id: u32, prefix: &str, children: &HashMap<u32, Vec<(u32, usize)>>, names: &HashMap<u32, String>, lines: &mut Vec<String>) {
+        let branches = children.get(&id).map_or(&[][..], Vec::as_slice);
+        for (i, (child, jump)) in branches.iter().enumerate() {
+            let last = i + 1 == branches.len();
+            lines.push(format!("{}{}{} [{}]", prefix, if last { "└── " } else { "├── " }, names.get(child).map_or("?", String::as_str), jump));
+            draw(*child, &format!("{}{}", prefix, if last { "    " } else { "│   " }), children, names, lines);
+        }
+    }
+
+    let mut lines = vec![names.get(&start_id).cloned().unwrap_or_else(|| "?".to_owned())];
+    draw(start_id, "", &children, names, &mut lines);
+    lines
+}
+
+/// The route positions where the pilot has to choose a gate, each with the system the next jump
+/// goes to. A system with one or two connections leaves no choice, whether passing along a pipe
+/// or turning back at a dead end, so turns are the route systems with more than two connections
+/// that the route jumps on from. Position 0 is the start.
+pub fn turns(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32]) -> Vec<(usize, u32, u32)> {
+    let degrees = graph.node_references().map(|(i, n)| (n.id, graph.neighbors(i).count())).collect::<HashMap<_, _>>();
+    std::iter::once(&start_id).chain(route).collect::<Vec<_>>().windows(2).enumerate()
+        .filter(|(_, w)| degrees.get(w[0]).is_some_and(|degree| *degree > 2))
+        .map(|(position, w)| (position, *w[0], *w[1]))
+        .collect()
+}
+
+/// Metres in an astronomical unit.
+pub const AU: f64 = 149_597_870_700.0;
+
+/// One line per jump naming both systems, with the distance between them in AU when both have
+/// coordinates.
+pub fn jump_lines(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32], names: &HashMap<u32, String>) -> Vec<String> {
+    let centers = graph.node_weights().map(|n| (n.id, n.center)).collect::<HashMap<_, _>>();
+    std::iter::once(&start_id).chain(route).collect::<Vec<_>>().windows(2).map(|w| {
+        let (from, to) = (*w[0], *w[1]);
+        let line = format!("{} -> {}", names.get(&from).map_or("?", String::as_str), names.get(&to).map_or("?", String::as_str));
+        match (centers.get(&from).copied().flatten(), centers.get(&to).copied().flatten()) {
+            (Some(a), Some(b)) => format!("{} ({:.1} AU)", line, distance(&a, &b) / AU),
+            _ => line,
+        }
+    }).collect()
+}
+
+/// Estimated remaining cost for astar towards a single target system.
+#[derive(Clone, Copy, Debug)]
+pub enum Heuristic {
+    /// No coordinates, astar behaves like Dijkstra
+    Zero,
+    /// Straight-line distance to the target, scaled by the smallest weight per unit of distance of
+    /// any connection so it never overestimates
+    Euclidean { target: [f64; 3], scale: f64 },
+}
+
+impl Heuristic {
+    pub fn new(graph: &Graph<System, Connection, Undirected>, target: NodeIndex) -> Heuristic {
+        let Some(target_center) = graph[target].center else {
+            return Heuristic::Zero;
+        };
+
+        let mut scale = f64::INFINITY;
+        for e in graph.edge_references() {
+            let (Some(a), Some(b)) = (graph[e.source()].center, graph[e.target()].center) else {
+                return Heuristic::Zero;
+            };
+            let length = distance(&a, &b);
+            if length > 0.0 {
+                scale = scale.min(e.weight().weight as f64 / length);
+            }
+        }
+
+        if scale.is_finite() && scale > 0.0 {
+            Heuristic::Euclidean { target: target_center, scale }
+        } else {
+            Heuristic::Zero
+        }
+    }
+
+    pub fn estimate(&self, system: &System) -> f32 {
+        match (self, system.center) {
+            (Heuristic::Euclidean { target, scale }, Some(center)) => (distance(&center, target) * scale) as f32,
+            _ => 0.0,
+        }
+    }
+}
+
+pub fn node_index_of<E>(graph: &Graph<System, E, Undirected>, id: u32) -> Option<NodeIndex> {
+    graph.node_references().find(|(_, system)| system.id == id).map(|(i, _)| i)
+}
+
+/// The system reachable by every member with the lowest total or furthest jump count, and each
+/// member's jumps to it. Ties go to the better value of the other metric, then the lowest ID.
+pub fn rally_point(graph: &Graph<System, Connection, Undirected>, members: &[NodeIndex], metric: RallyMetric) -> Option<(NodeIndex, Vec<usize>)> {
+    let distances = members.par_iter()
+        .map(|m| algo::dijkstra(graph, *m, None, |_| 1usize))
+        .collect::<Vec<_>>();
+
+    graph.node_indices().filter_map(|n| {
+        let jumps = distances.iter().map(|d| d.get(&n).copied()).collect::<Option<Vec<_>>>()?;
+        let sum = jumps.iter().sum::<usize>();
+        let max = jumps.iter().copied().max().unwrap_or_default();
+        let key = match metric {
+            RallyMetric::Sum => (sum, max),
+            RallyMetric::Max => (max, sum),
+        };
+        Some((key, graph[n].id, n, jumps))
+    }).min_by_key(|(key, id, _, _)| (*key, *id)).map(|(_, _, n, jumps)| (n, jumps))
+}
+
+/// Dead-end systems with a single connection, where any covering route has to turn back, with
+/// their hop distance from the start. Nearest first.
+pub fn region_leaves(graph: &Graph<System, Connection, Undirected>, start_index: NodeIndex) -> Vec<(NodeIndex, usize)> {
+    let distances = algo::dijkstra(graph, start_index, None, |_| 1usize);
+    let mut leaves = graph.node_indices()
+        .filter(|n| graph.neighbors(*n).count() == 1)
+        .map(|n| (n, distances.get(&n).copied().unwrap_or(usize::MAX)))
+        .collect::<Vec<_>>();
+    leaves.sort_by(|(a, da), (b, db)| da.cmp(db).then_with(|| graph[*a].name.cmp(&graph[*b].name)));
+    leaves
+}
+
+/// Connections whose removal would split the region, found with Tarjan's low-link DFS.
+pub fn bridges(graph: &Graph<System, Connection, Undirected>) -> HashSet<EdgeIndex> {
+    fn visit(graph: &Graph<System, Connection, Undirected>, n: NodeIndex, parent: Option<EdgeIndex>, order: &mut HashMap<NodeIndex, usize>, low: &mut HashMap<NodeIndex, usize>, bridges: &mut HashSet<EdgeIndex>) {
+        let discovered = order.len();
+        order.insert(n, discovered);
+        low.insert(n, discovered);
+
+        for e in graph.edges(n) {
+            if Some(e.id()) == parent {
+                continue;
+            }
+            let next = if e.source() == n { e.target() } else { e.source() };
+            match order.get(&next).copied() {
+                Some(next_order) => {
+                    low.insert(n, low[&n].min(next_order));
+                }
+                None => {
+                    visit(graph, next, Some(e.id()), order, low, bridges);
+                    low.insert(n, low[&n].min(low[&next]));
+                    if low[&next] > order[&n] {
+                        bridges.insert(e.id());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut order = HashMap::new();
+    let mut low = HashMap::new();
+    let mut bridges = HashSet::new();
+    for n in graph.node_indices() {
+        if !order.contains_key(&n) {
+            visit(graph, n, None, &mut order, &mut low, &mut bridges);
+        }
+    }
+    bridges
+}
+
+/// Groups of systems that stay connected after losing any one connection, largest first. Systems
+/// that are only reachable over a bridge form groups of one.
+pub fn blocks(graph: &Graph<System, Connection, Undirected>) -> Vec<Vec<NodeIndex>> {
+    let bridges = bridges(graph);
+    let mut components = UnionFind::<usize>::new(graph.node_count());
+    for e in graph.edge_references() {
+        if !bridges.contains(&e.id()) {
+            components.union(e.source().index(), e.target().index());
+        }
+    }
+
+    let mut blocks = BTreeMap::<usize, Vec<NodeIndex>>::new();
+    for n in graph.node_indices() {
+        blocks.entry(components.find(n.index())).or_default().push(n);
+    }
+    let mut blocks = blocks.into_values().collect::<Vec<_>>();
+    blocks.sort_by_key(|b| std::cmp::Reverse(b.len()));
+    blocks
+}
+
+/// Betweenness centrality of every system by jumps: the number of pairs of other systems whose
+/// shortest paths run through it, split evenly between equally short paths. Uses Brandes'
+/// algorithm with one breadth first search per system.
+pub fn betweenness(graph: &Graph<System, Connection, Undirected>) -> HashMap<NodeIndex, f64> {
+    let partials = graph.node_indices().par_bridge().map(|source| {
+        let mut order = Vec::new();
+        let mut predecessors = HashMap::<NodeIndex, Vec<NodeIndex>>::new();
+        let mut paths = HashMap::from([(source, 1.0)]);
+        let mut distances = HashMap::from([(source, 0usize)]);
+        let mut queue = VecDeque::from([source]);
+        while let Some(n) = queue.pop_front() {
+            order.push(n);
+            for next in graph.neighbors(n) {
+                if !distances.contains_key(&next) {
+                    distances.insert(next, distances[&n] + 1);
+                    queue.push_back(next);
+                }
+                if distances[&next] == distances[&n] + 1 {
+                    *paths.entry(next).or_insert(0.0) += paths[&n];
+                    predecessors.entry(next).or_default().push(n);
+                }
+            }
+        }
+
+        let mut dependency = HashMap::<NodeIndex, f64>::new();
+        let mut partial = HashMap::new();
+        for n in order.into_iter().rev() {
+            let share = (1.0 + dependency.get(&n).copied().unwrap_or_default()) / paths[&n];
+            for p in predecessors.get(&n).into_iter().flatten() {
+                *dependency.entry(*p).or_default() += paths[p] * share;
+            }
+            if n != source {
+                partial.insert(n, dependency.get(&n).copied().unwrap_or_default());
+            }
+        }
+        partial
+    }).collect::<Vec<_>>();
+
+    // Every pair is counted once from each end
+    let mut centrality = graph.node_indices().map(|n| (n, 0.0)).collect::<HashMap<_, _>>();
+    for (n, value) in partials.into_iter().flatten() {
+        *centrality.get_mut(&n).expect("Node disappeared") += value / 2.0;
+    }
+    centrality
+}
+
+/// Returns the pair of systems with the greatest hop distance between them, and that distance.
+pub fn region_extremes(graph: &Graph<System, Connection, Undirected>) -> Option<(NodeIndex, NodeIndex, usize)> {
+    graph.node_indices().par_bridge().flat_map_iter(|a| {
+        algo::dijkstra(graph, a, None, |_| 1usize).into_iter().map(move |(b, d)| (a, b, d))
+    }).max_by_key(|(a, b, d)| (*d, std::cmp::Reverse(sort_tuple((*a, *b)))))
+}
+
+/// Simple path searches estimated to enumerate more than this many paths are considered intractable.
+pub const TRACTABLE_PATHS: f64 = 1e8;
+
+/// Regions smaller than this get a note that the short route is expected.
+pub const SMALL_REGION: usize = 5;
+
+#[derive(Debug)]
+pub struct SampleEstimate {
+    pub max_length: usize,
+    pub paths: usize,
+    pub elapsed: Duration,
+    pub branching_factor: f64,
+    pub estimated_paths: f64,
+}
+
+impl SampleEstimate {
+    pub fn tractable(&self) -> bool {
+        self.estimated_paths <= TRACTABLE_PATHS
+    }
+}
+
+/// Enumerates simple paths from the start with a growing length cap until the time budget runs out,
+/// then extrapolates the growth rate to the full region.
+pub fn sample_search(graph: &Graph<System, Connection, Undirected>, start_index: NodeIndex, budget: Duration) -> SampleEstimate {
+    let started = Instant::now();
+    let mut counts = Vec::<usize>::new();
+
+    'caps: for max_length in 0..graph.node_count() {
+        let mut paths = 0;
+        for n in graph.node_indices() {
+            for _ in algo::all_simple_paths::<Vec<_>, _>(graph, start_index, n, 0, Some(max_length)) {
+                paths += 1;
+                if started.elapsed() > budget {
+                    break 'caps;
+                }
+            }
+        }
+        counts.push(paths);
+        if counts.len() > 1 && paths == counts[counts.len() - 2] {
+            break;
+        }
+    }
+
+    let paths = counts.last().copied().unwrap_or(0);
+    let branching_factor = match counts.as_slice() {
+        [.., previous, last] if *previous > 0 => (*last as f64 / *previous as f64).max(1.0),
+        _ => 1.0,
+    };
+
+    let max_length = counts.len().saturating_sub(1);
+    let remaining = graph.node_count().saturating_sub(max_length + 2) as i32;
+    let estimated_paths = paths as f64 * branching_factor.powi(remaining);
+
+    SampleEstimate {
+        max_length,
+        paths,
+        elapsed: started.elapsed(),
+        branching_factor,
+        estimated_paths,
+    }
+}
+
+/// Returns the obvious covering route when the graph is a single pipe or a single cycle (every system
+/// has at most two gates), or None if a search is needed. Pipes are walked longest branch first.
+pub fn trivial_route(graph: &Graph<System, Connection, Undirected>, start_id: u32) -> Option<Vec<u32>> {
+    if graph.node_indices().any(|n| graph.neighbors(n).count() > 2) {
+        return None;
+    }
+
+    let start_index = node_index_of(graph, start_id)?;
+    if Dfs::new(graph, start_index).iter(graph).count() != graph.node_count() {
+        return None;
+    }
+
+    let walk = |first: NodeIndex| {
+        let mut branch = vec![first];
+        let mut previous = start_index;
+        while let Some(next) = graph.neighbors(*branch.last().unwrap()).find(|n| *n != previous && *n != start_index) {
+            previous = *branch.last().unwrap();
+            branch.push(next);
+        }
+        branch
+    };
+
+    let mut branches = graph.neighbors(start_index).collect::<HashSet<_>>().into_iter().map(walk).collect::<Vec<_>>();
+
+    if graph.edge_count() == graph.node_count() {
+        // A cycle, go all the way around
+        let around = branches.into_iter().max_by_key(|b| (b.len(), std::cmp::Reverse(b[0]))).unwrap_or_default();
+        return Some(around.iter().map(|n| graph[*n].id).chain(std::iter::once(start_id)).collect());
+    }
+
+    branches.sort_by_key(|b| (std::cmp::Reverse(b.len()), b[0]));
+
+    let mut route = Vec::<u32>::new();
+    for branch in branches {
+        route.extend(branch.iter().map(|n| graph[*n].id));
+        route.extend(branch.iter().rev().skip(1).map(|n| graph[*n].id));
+        route.push(start_id);
+    }
+    Some(route)
+}
+
+#[derive(Clone, Default)]
+pub struct SearchOptions {
+    /// Maximum number of intermediate systems in each simple path considered
+    pub max_path_len: Option<usize>,
+    /// Stop enumerating paths after this instant, leaving a partial route
+    pub deadline: Option<Instant>,
+    /// Only come back to the start once the rest of its reachable systems are covered, instead of
+    /// after every segment
+    pub no_start_reentry: bool,
+    /// Ranks candidate outbound paths, longest wins when unset
+    pub scorer: Option<Scorer>,
+    /// Narrate each outbound segment and straggler pickup to stderr
+    pub explain: bool,
+    /// Systems the route may pass through but never detours to visit
+    pub transit_only: HashSet<u32>,
+    /// Enumerate paths with chains of pass-through systems contracted, ignored with max_path_len
+    /// or max_mem
+    pub contract_pipes: bool,
+    /// Rough ceiling in bytes on the memory held by path enumeration across the search threads
+    pub max_mem: Option<usize>,
+    /// Shared between every search using these options, including straggler pickups
+    pub memory: Arc<MemoryWatch>,
+    /// Plan one segment out from the start and back, then treat the start like any visited
+    /// system and pick up the rest along that segment
+    pub release_start: bool,
+    /// How many segments may pass back through the start before the last one ends there
+    pub max_start_revisits: Option<usize>,
+    /// Time allowed for each subgraph search, which stops enumerating at its own deadline
+    pub subgraph_timeout: Option<Duration>,
+    /// When the current subgraph search runs out of time, set per search from subgraph_timeout
+    pub subgraph_deadline: Option<Instant>,
+    /// Start system and size of each subgraph whose search ran out of its own time
+    pub time_limited: Arc<Mutex<Vec<(u32, usize)>>>,
+    /// Systems of path length given up per jump of the return leg a candidate path needs, zero
+    /// to ignore backtracking
+    pub revisit_penalty: f32,
+    /// Enumerate every simple path even in regions with more than EXACT_SEARCH_CYCLES cycles
+    pub exact: bool,
+    /// Report each new peak candidate path length and each segment found to stderr
+    pub progress: bool,
+}
+
+/// Estimated bytes held per system of a candidate path while enumerating: the visited set, the
+/// stack of neighbour iterators and the collected path and best path so far.
+pub const PATH_BYTES_PER_SYSTEM: usize = 80;
+
+/// Tracks the size of the candidate paths seen by the search.
+#[derive(Debug, Default)]
+pub struct MemoryWatch {
+    peak_path: AtomicUsize,
+    capped: AtomicUsize,
+}
+
+impl MemoryWatch {
+    /// Records a candidate path, returning whether it is the longest so far.
+    fn observe(&self, path_len: usize) -> bool {
+        self.peak_path.fetch_max(path_len, Ordering::Relaxed) < path_len
+    }
+
+    /// Systems in the longest candidate path enumerated so far.
+    pub fn peak_path(&self) -> usize {
+        self.peak_path.load(Ordering::Relaxed)
+    }
+
+    /// Estimated peak enumeration memory in bytes, as if every search thread held the longest path.
+    pub fn peak_bytes(&self) -> usize {
+        self.peak_path() * PATH_BYTES_PER_SYSTEM * rayon::current_num_threads()
+    }
+
+    /// How many path searches went over max_mem and were redone with a length cap.
+    pub fn capped(&self) -> usize {
+        self.capped.load(Ordering::Relaxed)
+    }
+}
+
+/// Parses a byte count such as `4096`, `512K`, `64M` or `2G`.
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.trim().parse::<usize>()
+        .map_err(|e| format!("invalid size {}: {}", size, e))
+        .and_then(|n| n.checked_mul(multiplier).ok_or_else(|| format!("size {} is too large", size)))
+}
+
+impl std::fmt::Debug for SearchOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchOptions")
+            .field("max_path_len", &self.max_path_len)
+            .field("deadline", &self.deadline)
+            .field("no_start_reentry", &self.no_start_reentry)
+            .field("scorer", &self.scorer.as_ref().map(|_| "custom"))
+            .field("explain", &self.explain)
+            .field("transit_only", &self.transit_only)
+            .field("contract_pipes", &self.contract_pipes)
+            .field("max_mem", &self.max_mem)
+            .field("memory", &self.memory)
+            .field("revisit_penalty", &self.revisit_penalty)
+            .field("exact", &self.exact)
+            .field("progress", &self.progress)
+            .field("release_start", &self.release_start)
+            .field("max_start_revisits", &self.max_start_revisits)
+            .field("subgraph_timeout", &self.subgraph_timeout)
+            .field("subgraph_deadline", &self.subgraph_deadline)
+            .field("time_limited", &self.time_limited)
+            .finish()
+    }
+}
+
+/// Ranks a candidate outbound path (including the start), the highest scoring path becomes the
+/// next segment. Scores are only compared with each other, so any scale will do. This runs for
+/// every simple path enumerated, which can be millions of times, so it needs to be cheap. The
+/// built-in scorers count in thousandths of a system, the scale the revisit penalty is charged in.
+pub type Scorer = Arc<dyn Fn(&[NodeIndex], &Graph<System, Connection, Undirected>) -> i64 + Send + Sync>;
+
+/// Prefers the path with the most systems.
+pub fn longest_scorer() -> Scorer {
+    Arc::new(|path, _| path.len() as i64 * 1000)
+}
+
+/// Prefers long paths, less `penalty` jumps for each system below 0.5 security.
+pub fn highsec_scorer(penalty: f32) -> Scorer {
+    penalty_scorer(penalty, 0.0)
+}
+
+/// Prefers long paths, less `penalty` jumps each time the path moves to another constellation.
+pub fn constellation_scorer(penalty: f32) -> Scorer {
+    penalty_scorer(0.0, penalty)
+}
+
+/// Prefers long paths, less the highsec and constellation penalties together.
+pub fn penalty_scorer(highsec: f32, constellation: f32) -> Scorer {
+    Arc::new(move |path, graph| {
+        let lowsec = path.iter().skip(1).filter(|n| !is_highsec(&graph[**n])).count();
+        let transitions = constellation_transitions(path.iter().map(|n| &graph[*n]));
+        ((path.len() as f32 - highsec * lowsec as f32 - constellation * transitions as f32) * 1000.0) as i64
+    })
+}
+
+/// Moves between systems in different constellations, systems without constellation data never
+/// count as a move.
+pub fn constellation_transitions<'a>(systems: impl IntoIterator<Item = &'a System>) -> usize {
+    let mut previous = None;
+    let mut transitions = 0;
+    for system in systems {
+        if let Some(constellation) = system.constellation_id {
+            if previous.is_some_and(|p| p != constellation) {
+                transitions += 1;
+            }
+            previous = Some(constellation);
+        }
+    }
+    transitions
+}
+
+impl SearchOptions {
+    /// How desirable a candidate outbound path is, higher is better. Transit-only systems add
+    /// nothing, so a path isn't preferred for passing through them.
+    pub fn score(&self, graph: &Graph<System, Connection, Undirected>, path: &[NodeIndex]) -> i64 {
+        let transit = path.iter().skip(1).filter(|n| self.transit_only.contains(&graph[**n].id)).count() as i64;
+        let score = match &self.scorer {
+            Some(scorer) => scorer(path, graph),
+            None => path.len() as i64 * 1000,
+        };
+        score - transit * 1000
+    }
+
+    pub fn timed_out(&self) -> bool {
+        let now = Instant::now();
+        self.deadline.is_some_and(|deadline| now > deadline) || self.subgraph_deadline.is_some_and(|deadline| now > deadline)
+    }
+
+    /// Records a candidate path with the memory watch, reporting a new peak with progress on.
+    fn observe(&self, path_len: usize) {
+        if self.memory.observe(path_len) && self.progress {
+            eprintln!("Peak candidate path: {} systems, about {} KiB of enumeration state", path_len, self.memory.peak_bytes().div_ceil(1024));
+        }
+    }
+
+    /// Longest candidate path, in systems, that keeps enumeration on every thread within max_mem.
+    pub fn memory_cap(&self) -> Option<usize> {
+        self.max_mem.map(|bytes| (bytes / (PATH_BYTES_PER_SYSTEM * rayon::current_num_threads())).max(2))
+    }
+}
+
+/// Graph view that stops yielding neighbours once the search deadline passes, so path enumeration
+/// unwinds promptly even while no path is reaching its target.
+#[derive(Clone, Copy)]
+struct Cutoff<'a, G> {
+    graph: G,
+    options: &'a SearchOptions,
+}
+
+impl<G> Cutoff<'_, G> {
+    fn limit(&self) -> usize {
+        if self.options.timed_out() { 0 } else { usize::MAX }
+    }
+}
+
+impl<G: GraphBase> GraphBase for Cutoff<'_, G> {
+    type EdgeId = G::EdgeId;
+    type NodeId = G::NodeId;
+}
+
+impl<G: GraphRef> GraphRef for Cutoff<'_, G> {}
+
+impl<G: NodeCount> NodeCount for Cutoff<'_, G> {
+    fn node_count(&self) -> usize {
+        self.graph.node_count()
+    }
+}
+
+impl<G: IntoNeighbors> IntoNeighbors for Cutoff<'_, G> {
+    type Neighbors = std::iter::Take<G::Neighbors>;
+
+    fn neighbors(self, a: G::NodeId) -> Self::Neighbors {
+        self.graph.neighbors(a).take(self.limit())
+    }
+}
+
+impl<G: IntoNeighborsDirected> IntoNeighborsDirected for Cutoff<'_, G> {
+    type NeighborsDirected = std::iter::Take<G::NeighborsDirected>;
+
+    fn neighbors_directed(self, a: G::NodeId, d: Direction) -> Self::NeighborsDirected {
+        self.graph.neighbors_directed(a, d).take(self.limit())
+    }
+}
+
+/// A region with each chain of pass-through systems (exactly two connections, not the start)
+/// replaced by a single connection, so path enumeration only branches at junctions.
+struct Contracted {
+    graph: Graph<System, Connection, Undirected>,
+    /// Systems inside each contracted chain, in order from the first system of the key
+    chains: HashMap<(u32, u32), Vec<u32>>,
+    /// Chains by each of their end systems
+    chain_ends: HashMap<u32, Vec<(u32, u32)>>,
+}
+
+impl Contracted {
+    /// Chains that loop back to one system, run parallel to a connection or another chain, or
+    /// make up a whole cycle are left as they are.
+    fn new(graph: &Graph<System, Connection, Undirected>, start_id: u32) -> Contracted {
+        let pass_through = |n: NodeIndex| graph[n].id != start_id && graph.neighbors(n).count() == 2;
+
+        let mut seen = HashSet::<NodeIndex>::new();
+        let mut removed = HashSet::<NodeIndex>::new();
+        let mut chains = HashMap::<(u32, u32), Vec<u32>>::new();
+        let mut chain_edges = Vec::<(NodeIndex, NodeIndex, f32)>::new();
+
+        for n in graph.node_indices() {
+            if !pass_through(n) || !seen.insert(n) {
+                continue;
+            }
+
+            // Walk out both sides to the systems ending the chain
+            let sides = graph.neighbors(n).map(|first| {
+                let (mut previous, mut current) = (n, first);
+                let mut inside = Vec::new();
+                while current != n && pass_through(current) {
+                    inside.push(current);
+                    let next = graph.neighbors(current).find(|x| *x != previous).expect("Chain system lost a connection");
+                    (previous, current) = (current, next);
+                }
+                (current, inside)
+            }).collect::<Vec<_>>();
+            let [(a, left), (b, right)] = &sides[..] else { continue };
+            seen.extend(left.iter().chain(right));
+
+            let key = (graph[*a].id, graph[*b].id);
+            if a == b || *a == n || graph.find_edge(*a, *b).is_some() || chains.contains_key(&key) || chains.contains_key(&(key.1, key.0)) {
+                continue;
+            }
+
+            let inside = left.iter().rev().chain([&n]).chain(right).copied().collect::<Vec<_>>();
+            let weight = std::iter::once(*a).chain(inside.iter().copied()).chain([*b]).collect::<Vec<_>>()
+                .windows(2)
+                .map(|w| graph[graph.find_edge(w[0], w[1]).expect("Chain connection disappeared")].weight)
+                .sum();
+            removed.extend(inside.iter().copied());
+            chains.insert(key, inside.iter().map(|i| graph[*i].id).collect());
+            chain_edges.push((*a, *b, weight));
+        }
+
+        let mut contracted = filter_nodes(graph, |i, _| !removed.contains(&i));
+        for (a, b, weight) in chain_edges {
+            let a = node_index_of(&contracted, graph[a].id).expect("Chain end disappeared");
+            let b = node_index_of(&contracted, graph[b].id).expect("Chain end disappeared");
+            contracted.add_edge(a, b, Connection { weight, ..Connection::new(ConnectionKind::Gate) });
+        }
+
+        let mut chain_ends = HashMap::<u32, Vec<(u32, u32)>>::new();
+        for key in chains.keys() {
+            chain_ends.entry(key.0).or_default().push(*key);
+            chain_ends.entry(key.1).or_default().push(*key);
+        }
+
+        Contracted { graph: contracted, chains, chain_ends }
+    }
+
+    /// System IDs along a path over the contracted graph. A path that can't go on to a new junction
+    /// may still run down a chain towards a system it already passed, so it is extended along the
+    /// longest such chain, as the uncontracted search would have found.
+    fn expand(&self, path: &[NodeIndex]) -> Vec<u32> {
+        let mut ids = vec![self.graph[path[0]].id];
+        for w in path.windows(2) {
+            let (a, b) = (self.graph[w[0]].id, self.graph[w[1]].id);
+            if let Some(inside) = self.chains.get(&(a, b)) {
+                ids.extend(inside);
+            } else if let Some(inside) = self.chains.get(&(b, a)) {
+                ids.extend(inside.iter().rev());
+            }
+            ids.push(b);
+        }
+
+        if let Some((key, forward)) = self.extension(path) {
+            if forward {
+                ids.extend(&self.chains[&key]);
+            } else {
+                ids.extend(self.chains[&key].iter().rev());
+            }
+        }
+        ids
+    }
+
+    /// Number of systems expand would return, without building the path.
+    fn expanded_len(&self, path: &[NodeIndex]) -> usize {
+        let chains = path.windows(2).map(|w| {
+            let (a, b) = (self.graph[w[0]].id, self.graph[w[1]].id);
+            self.chains.get(&(a, b)).or_else(|| self.chains.get(&(b, a))).map_or(0, Vec::len)
+        }).sum::<usize>();
+        let extension = self.extension(path).map_or(0, |(key, _)| self.chains[&key].len());
+        path.len() + chains + extension
+    }
+
+    /// The longest unused chain from the end of the path back to a system on it, and whether it
+    /// runs in key order. Paths that already came back to their first system end there.
+    fn extension(&self, path: &[NodeIndex]) -> Option<((u32, u32), bool)> {
+        let end = self.graph[*path.last()?].id;
+        if path.len() > 1 && end == self.graph[path[0]].id {
+            return None;
+        }
+        let previous = path.len().checked_sub(2).map(|i| self.graph[path[i]].id);
+        let on_path = |id: u32| path.iter().any(|n| self.graph[*n].id == id);
+
+        self.chain_ends.get(&end)?.iter().filter_map(|key| {
+            let (other, forward) = if key.0 == end { (key.1, true) } else { (key.0, false) };
+            (Some(other) != previous && on_path(other)).then_some((*key, forward))
+        }).max_by_key(|(key, _)| self.chains[key].len())
+    }
+}
+
+/// Paths counted by count_optima stop being counted beyond this many.
+pub const OPTIMA_CAP: usize = 100_000;
+
+#[derive(Debug)]
+pub struct Optima {
+    /// Jumps in the longest simple path from the start
+    pub jumps: usize,
+    /// Number of simple paths of that length, saturating at OPTIMA_CAP
+    pub count: usize,
+    /// Distinct systems where the longest paths end
+    pub endpoints: Vec<NodeIndex>,
+}
+
+/// Counts the simple paths from the start that tie for the longest, as the first segment of
+/// find_longest_paths would choose between.
+pub fn count_optima(graph: &Graph<System, Connection, Undirected>, start_index: NodeIndex, options: &SearchOptions) -> Optima {
+    let per_target = graph.node_indices().par_bridge().filter_map(|n| {
+        let cutoff = Cutoff { graph, options };
+        let mut best = None::<(usize, usize)>;
+        for path in algo::all_simple_paths::<Vec<_>, _>(cutoff, start_index, n, 0, options.max_path_len) {
+            best = match best {
+                Some((len, count)) if path.len() == len => Some((len, (count + 1).min(OPTIMA_CAP))),
+                Some((len, count)) if path.len() < len => Some((len, count)),
+                _ => Some((path.len(), 1)),
+            };
+        }
+        best.map(|(len, count)| (n, len, count))
+    }).collect::<Vec<_>>();
+
+    let longest = per_target.iter().map(|(_, len, _)| *len).max().unwrap_or(1);
+    let mut endpoints = per_target.iter().filter(|(_, len, _)| *len == longest).map(|(n, _, _)| *n).collect::<Vec<_>>();
+    endpoints.sort();
+
+    Optima {
+        jumps: longest - 1,
+        count: per_target.iter().filter(|(_, len, _)| *len == longest).map(|(_, _, count)| count).sum::<usize>().min(OPTIMA_CAP),
+        endpoints,
+    }
+}
+
+/// Shared by every level of one search, so each system seeds a straggler search at most once
+/// however the recursion unfolds.
+#[derive(Debug, Default)]
+pub struct SearchGuard {
+    seeded: Mutex<HashSet<u32>>,
+    subgraphs: AtomicUsize,
+}
+
+impl SearchGuard {
+    /// Records a system as a search seed, false if it already was one.
+    fn seed(&self, id: u32) -> bool {
+        self.seeded.lock().expect("Search guard poisoned").insert(id)
+    }
+
+    fn count_subgraph(&self) {
+        self.subgraphs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many subgraphs the search has built so far.
+    pub fn subgraphs(&self) -> usize {
+        self.subgraphs.load(Ordering::Relaxed)
+    }
+}
+
+/// Most independent cycles (connections - systems + 1) a region can have for its segments to be
+/// found by enumerating every simple path. The number of paths grows exponentially with the
+/// cycles rather than the systems, so a large tree is still searched exactly.
+pub const EXACT_SEARCH_CYCLES: usize = 30;
+
+/// Partial paths kept between steps of the beam search.
+pub const BEAM_WIDTH: usize = 64;
+
+/// Systems reachable from `from` without entering a visited system, counting `from` itself.
+fn reachable_unvisited(graph: &Graph<System, Connection, Undirected>, from: NodeIndex, visited: &[bool]) -> usize {
+    let mut seen = visited.to_vec();
+    seen[from.index()] = true;
+    let mut stack = vec![from];
+    let mut count = 0;
+    while let Some(node) = stack.pop() {
+        count += 1;
+        for next in graph.neighbors(node) {
+            if !seen[next.index()] {
+                seen[next.index()] = true;
+                stack.push(next);
+            }
+        }
+    }
+    count
+}
+
+/// A long simple path from the start by beam search, for regions too big to enumerate. Each step
+/// extends every kept path by one system and keeps those whose rank plus the unvisited systems
+/// they can still reach, an upper bound on how much further they can grow, is highest,
+/// preferring the fewest onward options so dead ends are taken before they are cut off. The best
+/// ranked path of any step wins. The beam holds a fixed number of paths, so max_mem and
+/// contract_pipes don't apply.
+fn beam_longest_path(graph: &Graph<System, Connection, Undirected>, start: NodeIndex, options: &SearchOptions, rank: &(dyn Fn(&Vec<NodeIndex>) -> i64 + Sync)) -> Option<Vec<NodeIndex>> {
+    let limit = options.max_path_len.map_or(usize::MAX, |len| len + 2);
+    let mut visited = vec![false; graph.node_count()];
+    visited[start.index()] = true;
+    let mut beam = vec![(vec![start], visited)];
+    let mut best: Option<(i64, Vec<NodeIndex>)> = None;
+
+    while beam[0].0.len() < limit && !options.timed_out() {
+        let mut candidates = beam.par_iter().flat_map_iter(|(path, visited)| {
+            let end = *path.last().expect("Got an empty path");
+            let mut next = graph.neighbors(end).filter(|n| !visited[n.index()]).collect::<Vec<_>>();
+            next.sort();
+            next.dedup();
+            next.into_iter().map(|next| {
+                let mut visited = visited.clone();
+                visited[next.index()] = true;
+                let onward = graph.neighbors(next).filter(|n| !visited[n.index()]).count();
+                let reach = reachable_unvisited(graph, next, &visited);
+                let mut path = path.clone();
+                path.push(next);
+                options.observe(path.len());
+                let bound = rank(&path).saturating_add(reach as i64 * 1000);
+                (std::cmp::Reverse(bound), onward, path, visited)
+            }).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        if candidates.is_empty() {
+            break;
+        }
+
+        candidates.sort_by_key(|(reach, onward, _, _)| (*reach, *onward));
+        // Paths through the same systems to the same end can only grow the same way
+        let mut kept = HashSet::new();
+        beam = candidates.into_iter()
+            .filter(|(_, _, path, visited)| kept.insert((*path.last().expect("Got an empty path"), visited.clone())))
+            .take(BEAM_WIDTH)
+            .map(|(_, _, path, visited)| (path, visited))
+            .collect();
+        for (path, _) in &beam {
+            let score = rank(path);
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, path.clone()));
+            }
+        }
+    }
+
+    best.map(|(_, path)| path)
+}
+
+pub fn find_longest_paths(original_graph: Graph<System, Connection, Undirected>, start_id: u32, options: &SearchOptions) -> Vec<u32> {
+    let guard = SearchGuard::default();
+    guard.seed(start_id);
+    find_longest_paths_guarded(original_graph, start_id, options, &guard)
+}
+
+fn find_longest_paths_guarded(original_graph: Graph<System, Connection, Undirected>, start_id: u32, options: &SearchOptions, guard: &SearchGuard) -> Vec<u32> {
+    let limited;
+    let options = match options.subgraph_timeout {
+        Some(timeout) => {
+            limited = SearchOptions {
+                subgraph_deadline: Some(Instant::now() + timeout),
+                ..options.clone()
+            };
+            &limited
+        }
+        None => options,
+    };
+
+    let original_graph = prune_transit_leaves(original_graph, start_id, &options.transit_only);
+
+    // The obvious walk goes longest branch first, which only suits a graph without transit-only
+    // systems to discount
+    let has_transit = original_graph.node_weights().any(|n| n.id != start_id && options.transit_only.contains(&n.id));
+    match trivial_route(&original_graph, start_id).filter(|_| !has_transit) {
+        Some(route) => {
+            if options.explain && !route.is_empty() {
+                let start = original_graph.node_weights().find(|n| n.id == start_id).expect("Start node disappeared");
+                eprintln!("From {}, the region is a single pipe or cycle, walking it end to end in {} jumps", start.name, route.len());
+            }
+            route
+        }
+        None => search_longest_paths(original_graph, start_id, options, guard),
+    }
+}
+
+/// Repeatedly drops transit-only systems that are dead ends, so no branch is entered just to
+/// reach them. Transit-only systems between other systems are kept.
+pub fn prune_transit_leaves(mut graph: Graph<System, Connection, Undirected>, start_id: u32, transit_only: &HashSet<u32>) -> Graph<System, Connection, Undirected> {
+    loop {
+        let dead_ends = graph.node_indices()
+            .filter(|n| graph[*n].id != start_id && transit_only.contains(&graph[*n].id) && graph.neighbors(*n).count() <= 1)
+            .collect::<HashSet<_>>();
+        if dead_ends.is_empty() {
+            return graph;
+        }
+        graph = filter_nodes(&graph, |i, _| !dead_ends.contains(&i));
+    }
+}
+
+/// Runs the search with progressively smaller path length caps until one finishes before the
+/// timeout, returning the route and the cap that succeeded.
+pub fn find_longest_paths_with_retry(graph: &Graph<System, Connection, Undirected>, start_id: u32, base_options: &SearchOptions, timeout: Duration) -> (Vec<u32>, Option<usize>) {
+    let mut cap = base_options.max_path_len;
+
+    loop {
+        let options = SearchOptions {
+            max_path_len: cap,
+            deadline: Some(Instant::now() + timeout),
+            ..base_options.clone()
+        };
+
+        let route = find_longest_paths(graph.clone(), start_id, &options);
+
+        let next_cap = cap.unwrap_or(graph.node_count()) / 2;
+        if !options.timed_out() || next_cap == 0 {
+            return (route, cap);
+        }
+
+        cap = Some(next_cap);
+    }
+}
+
+fn search_longest_paths(original_graph: Graph<System, Connection, Undirected>, start_id: u32, options: &SearchOptions, guard: &SearchGuard) -> Vec<u32> {
+    let mut graph = original_graph.clone();
+    let mut result = Vec::<u32>::new();
+    let mut segments = 0;
+
+    loop {
+        let start_index = graph.node_references().find(|(_, system)| {
+            system.id == start_id
+        }).expect("Start node disappeared").0;
+
+        // The return leg mostly retraces systems the segment just visited, so with a revisit
+        // penalty each candidate is charged for the cost of getting back from where it ends
+        let returns = (options.revisit_penalty > 0.0).then(|| algo::dijkstra(&graph, start_index, None, |e| e.weight().weight));
+        let rank = |v: &Vec<NodeIndex>| {
+            let back = returns.as_ref().and_then(|returns| v.last().and_then(|end| returns.get(end))).copied().unwrap_or_default();
+            options.score(&graph, v) - (options.revisit_penalty * back * 1000.0) as i64
+        };
+
+        let cycles = (graph.edge_count() + 1).saturating_sub(graph.node_count());
+        let longest_path = if !options.exact && cycles > EXACT_SEARCH_CYCLES {
+            beam_longest_path(&graph, start_index, options, &rank)
+        } else if options.contract_pipes && options.max_path_len.is_none() && options.max_mem.is_none() {
+            let contracted = Contracted::new(&graph, start_id);
+            let contracted_start = node_index_of(&contracted.graph, start_id).expect("Start node disappeared");
+            let index = graph.node_references().map(|(i, n)| (n.id, i)).collect::<HashMap<_, _>>();
+            contracted.graph.node_indices().par_bridge().filter_map(|n| {
+                let cutoff = Cutoff { graph: &contracted.graph, options };
+                let paths = algo::all_simple_paths::<Vec<_>, _>(cutoff, contracted_start, n, 0, None);
+                let expand = |path: Vec<NodeIndex>| contracted.expand(&path).iter().map(|id| index[id]).collect::<Vec<_>>();
+                if options.scorer.is_none() && returns.is_none() && options.transit_only.is_empty() {
+                    paths.max_by_key(|path| contracted.expanded_len(path)).map(expand)
+                } else {
+                    paths.map(expand).max_by_key(rank)
+                }
+            }).max_by_key(rank)
+        } else {
+            graph.node_indices().par_bridge().filter_map(|n| {
+                let cutoff = Cutoff { graph: &graph, options };
+                let paths = algo::all_simple_paths(cutoff, start_index, n, 0, options.max_path_len)
+                    .inspect(|v: &Vec<NodeIndex>| options.observe(v.len()));
+                let Some(cap) = options.memory_cap() else {
+                    return paths.max_by_key(rank);
+                };
+
+                // Abandon this target once a candidate outgrows the memory cap and search it
+                // again with the path length capped instead
+                let mut exceeded = false;
+                let longest = paths.take_while(|v| {
+                    exceeded = v.len() > cap;
+                    !exceeded
+                }).max_by_key(rank);
+                if !exceeded {
+                    return longest;
+                }
+                options.memory.capped.fetch_add(1, Ordering::Relaxed);
+                let max_intermediate = options.max_path_len.map_or(cap - 2, |len| len.min(cap - 2));
+                algo::all_simple_paths(cutoff, start_index, n, 0, Some(max_intermediate)).max_by_key(rank)
+            }).max_by_key(rank)
+        };
+
+        if let Some(longest_path) = longest_path {
+            let longest_path = longest_path.into_iter().skip(1).collect::<Vec<_>>();
+
+            if options.explain {
+                let end = *longest_path.last().expect("Got an empty path");
+                if end == start_index {
+                    eprintln!("From {}, longest loop comes back in {} jumps", graph[start_index].name, longest_path.len());
+                } else {
+                    eprintln!("From {}, longest branch reaches {} in {} jumps", graph[start_index].name, graph[end].name, longest_path.len());
+                }
+            }
+
+            if options.no_start_reentry {
+                result.extend(longest_path.iter().map(|n| graph[*n].id));
+                break;
+            }
+
+            let heuristic = Heuristic::new(&graph, start_index);
+            let return_path = algo::astar(
+                &graph,
+                *longest_path.last().expect("Got an empty path"),
+                |n| n == start_index,
+                |e| e.weight().weight,
+                |n| heuristic.estimate(&graph[n]),
+            ).expect("Cannot return to start").1.into_iter().skip(1).collect::<Vec<_>>();
+
+            if options.explain && !return_path.is_empty() {
+                eprintln!("  returning via {} in {} jumps", graph[return_path[0]].name, return_path.len());
+            }
+            
+            let full_path = longest_path.into_iter().chain(return_path).collect::<Vec<_>>();
+            let full_path_id = full_path.iter().map(|n| graph[*n].id).collect::<Vec<_>>();
+
+            result.extend(full_path_id);
+
+            graph = filter_nodes(&graph, |i, _| !full_path.contains(&i) || i == start_index);
+            guard.count_subgraph();
+
+            // Every segment ends at the start, so all but the last are passes back through it
+            segments += 1;
+            if options.progress {
+                eprintln!("From {}, segment {} takes {} jumps, {} of {} systems left",
+                    original_graph[node_index_of(&original_graph, start_id).expect("Start node disappeared")].name,
+                    segments, full_path.len(), graph.node_count() - 1, original_graph.node_count() - 1);
+            }
+            if options.release_start || options.max_start_revisits.is_some_and(|max| segments > max) {
+                break;
+            }
+        } else {
+            break;
+        }
+    }
+
+    if options.subgraph_deadline.is_some_and(|deadline| Instant::now() > deadline) {
+        options.time_limited.lock().expect("Time limited list poisoned").push((start_id, original_graph.node_count()));
+    }
+
+    // Systems left over hang off the route in separate components, each picked up from the first
+    // route system next to it. No two pickups share a system, so they are searched in parallel.
+    let leftover = |n: &System| !result.contains(&n.id) && n.id != start_id;
+    let mut components = UnionFind::<usize>::new(original_graph.node_count());
+    for e in original_graph.edge_references() {
+        if leftover(&original_graph[e.source()]) && leftover(&original_graph[e.target()]) {
+            components.union(e.source().index(), e.target().index());
+        }
+    }
+
+    // Leftovers made up only of transit-only systems aren't worth a detour
+    let mut claimed = original_graph.node_references()
+        .filter(|(_, n)| leftover(n))
+        .map(|(i, _)| components.find(i.index()))
+        .collect::<HashSet<_>>();
+    for (i, n) in original_graph.node_references() {
+        if leftover(n) && !options.transit_only.contains(&n.id) {
+            claimed.remove(&components.find(i.index()));
+        }
+    }
+    let mut visited = HashSet::<u32>::new();
+    visited.insert(start_id);
+    let mut pickups = Vec::<(u32, HashSet<usize>)>::new();
+
+    for id in &result {
+        if visited.insert(*id) {
+            let index = node_index_of(&original_graph, *id).expect("Route node disappeared");
+            let owned = original_graph.neighbors(index)
+                .filter(|n| leftover(&original_graph[*n]))
+                .map(|n| components.find(n.index()))
+                .filter(|c| !claimed.contains(c))
+                .collect::<HashSet<_>>();
+            // Components are only claimed once the seed is accepted, so a refused seed leaves them
+            // for a later route system next to them
+            if !owned.is_empty() && guard.seed(*id) {
+                claimed.extend(owned.iter().copied());
+                pickups.push((*id, owned));
+            }
+        }
+    }
+
+    // Pickups run concurrently, so only this level narrates to keep the explanation readable.
+    // They never reach the start, so they can come back to their own system as often as needed.
+    let pickup_options = SearchOptions {
+        explain: false,
+        max_start_revisits: None,
+        ..options.clone()
+    };
+    let mut stragglers = pickups.into_par_iter().map(|(id, owned)| {
+        let sub_graph = filter_nodes(&original_graph, |i, n| n.id == id || (leftover(n) && owned.contains(&components.find(i.index()))));
+        guard.count_subgraph();
+        (id, find_longest_paths_guarded(sub_graph, id, &pickup_options, guard))
+    }).collect::<HashMap<_, _>>();
+
+    if options.explain && !stragglers.is_empty() {
+        let name = |id: u32| &original_graph[node_index_of(&original_graph, id).expect("Route node disappeared")].name;
+        let mut seen = HashSet::new();
+        let detours = result.iter()
+            .filter(|id| seen.insert(**id))
+            .filter_map(|id| stragglers.get(id).map(|path| format!("{} (detour +{})", name(*id), path.len())))
+            .collect::<Vec<_>>();
+        eprintln!("Remaining stragglers: {}", detours.join(", "));
+    }
+
+    let mut final_result = Vec::<u32>::new();
+
+    for id in &result {
+        final_result.push(*id);
+
+        if let Some(path) = stragglers.remove(id) {
+            final_result.extend(path);
+        }
+    }
+
+    if options.no_start_reentry && !final_result.is_empty() {
+        let start_index = node_index_of(&original_graph, start_id).expect("Start node disappeared");
+        let last_index = node_index_of(&original_graph, *final_result.last().unwrap()).expect("Route node disappeared");
+
+        let heuristic = Heuristic::new(&original_graph, start_index);
+        let return_path = algo::astar(
+            &original_graph,
+            last_index,
+            |n| n == start_index,
+            |e| e.weight().weight,
+            |n| heuristic.estimate(&original_graph[n]),
+        ).expect("Cannot return to start").1;
+        final_result.extend(return_path.into_iter().skip(1).map(|n| original_graph[n].id));
+
+        // Branches that only connect through the start can't be reached without coming back to it
+        let leftover = filter_nodes(&original_graph, |_, n| !final_result.contains(&n.id) || n.id == start_id);
+        let leftover_options = SearchOptions {
+            no_start_reentry: false,
+            ..options.clone()
+        };
+        guard.count_subgraph();
+        final_result.extend(find_longest_paths_guarded(leftover, start_id, &leftover_options, guard));
+    } else if options.release_start {
+        // After the first segment the rest is picked up along the route, only branches that
+        // connect through the start alone need the route to come back to it
+        let leftover = filter_nodes(&original_graph, |_, n| !final_result.contains(&n.id) || n.id == start_id);
+        let leftover_options = SearchOptions {
+            release_start: false,
+            ..options.clone()
+        };
+        guard.count_subgraph();
+        final_result.extend(find_longest_paths_guarded(leftover, start_id, &leftover_options, guard));
+    }
+
+    final_result
+}
+
+pub fn load_aliases(path: &str) -> Result<HashMap<String, u32>, RouteError> {
+    read_json(path)
+}
+
+/// The system ID an alias stands for, as a string to look up like any name, other names unchanged.
+pub fn resolve_alias(aliases: &HashMap<String, u32>, name: &str) -> String {
+    aliases.get(name).map_or_else(|| name.to_owned(), u32::to_string)
+}
+
+/// Appends each system's aliases to its displayed name, as `Name (alias, alias)`.
+pub fn show_aliases(names: &mut HashMap<u32, String>, aliases: &HashMap<String, u32>) {
+    let mut by_system = BTreeMap::<u32, Vec<&str>>::new();
+    for (alias, id) in aliases {
+        by_system.entry(*id).or_default().push(alias);
+    }
+    for (id, mut system_aliases) in by_system {
+        if let Some(name) = names.get_mut(&id) {
+            system_aliases.sort();
+            *name = format!("{} ({})", name, system_aliases.join(", "));
+        }
+    }
+}
+
+pub fn load_route(path: &str) -> Result<Vec<u32>, RouteError> {
+    read_json(path)
+}
+
+/// Resolves the systems the route must begin with, checking each is connected to the one before
+/// it, starting from the start system.
+pub fn pinned_prefix(graph: &Graph<System, Connection, Undirected>, start_id: u32, names: &[String]) -> Result<Vec<u32>, RouteError> {
+    let mut current = node_index_of(graph, start_id).expect("Start node disappeared");
+    let mut prefix = Vec::new();
+    for name in names {
+        let next = find_system(graph, name).ok_or_else(|| RouteError::Disconnected(format!("Prefix system {} is not in the region", name)))?;
+        if next == current && prefix.is_empty() {
+            continue;
+        }
+        if graph.find_edge(current, next).is_none() {
+            return Err(RouteError::Disconnected(format!("Prefix system {} is not connected to {}", graph[next].name, graph[current].name)));
+        }
+        prefix.push(graph[next].id);
+        current = next;
+    }
+    Ok(prefix)
+}
+
+pub fn resume_route(graph: &Graph<System, Connection, Undirected>, previous: Vec<u32>, start_id: u32, options: &SearchOptions) -> Result<Vec<u32>, RouteError> {
+    let mut current_id = previous.last().copied().unwrap_or(start_id);
+    let mut visited = previous.iter().copied().collect::<HashSet<_>>();
+    visited.insert(start_id);
+
+    for id in &visited {
+        if !graph.node_weights().any(|n| n.id == *id) {
+            return Err(RouteError::Disconnected(format!("Resumed system {} is not in the region", id)));
+        }
+    }
+
+    let mut route = previous;
+
+    loop {
+        let remaining = filter_nodes(graph, |_, n| !visited.contains(&n.id) || n.id == current_id);
+        let path = find_longest_paths(remaining, current_id, options);
+        visited.extend(path.iter().copied());
+        route.extend(path);
+
+        // Systems cut off by the visited set are reached by travelling back through it
+        let current_index = graph.node_references().find(|(_, system)| {
+            system.id == current_id
+        }).expect("Resume node disappeared").0;
+
+        if let Some((_, bridge)) = algo::astar(
+            graph,
+            current_index,
+            |n| !visited.contains(&graph[n].id),
+            |e| e.weight().weight,
+            |_| 0.0,
+        ) {
+            let bridge_id = bridge.into_iter().skip(1).map(|n| graph[n].id).collect::<Vec<_>>();
+            current_id = *bridge_id.last().expect("Got an empty path");
+            visited.extend(bridge_id.iter().copied());
+            route.extend(bridge_id);
+        } else {
+            break;
+        }
+    }
+
+    Ok(route)
+}
+
+/// Target sets up to this size are ordered exactly, larger ones by the cheaper of a nearest
+/// neighbour and a spanning tree tour.
+pub const EXACT_TARGETS: usize = 12;
+
+/// Splits an `X:Y` argument into its two systems. System names can contain `:` themselves, so
+/// every split is tried and exactly one has to name two systems.
+pub fn parse_system_pair(spec: &str, lookup: impl Fn(&str) -> Option<u32>) -> Result<(u32, u32), String> {
+    let splits = spec.match_indices(':')
+        .filter_map(|(i, _)| lookup(&spec[..i]).zip(lookup(&spec[i + 1..])))
+        .collect::<Vec<_>>();
+    match splits[..] {
+        [pair] => Ok(pair),
+        [] => Err(format!("{} doesn't name two systems as X:Y", spec)),
+        _ => Err(format!("{} can be split into systems more than one way, use IDs", spec)),
+    }
+}
+
+/// Whether the "visit X before Y" constraints contradict each other.
+pub fn precedence_cycle(before: &[(u32, u32)]) -> bool {
+    algo::is_cyclic_directed(&petgraph::graphmap::DiGraphMap::<u32, ()>::from_edges(before))
+}
+
+/// Visits every target from the start in the order with the lowest total weight, following the
+/// shortest path between each, and stops at the last target, or with `round_trip` returns to the
+/// start as a closed loop. Each `(x, y)` in `before` makes the route reach x before y, both are
+/// visited even if they aren't targets.
+pub fn visit_targets(graph: &Graph<System, Connection, Undirected>, start_id: u32, targets: &[u32], before: &[(u32, u32)], round_trip: bool) -> Vec<u32> {
+    let mut stops = vec![node_index_of(graph, start_id).expect("Start node disappeared")];
+    for id in targets.iter().chain(before.iter().flat_map(|(x, y)| [x, y])) {
+        let index = node_index_of(graph, *id).expect("Target system is not in the region");
+        if !stops.contains(&index) {
+            stops.push(index);
+        }
+    }
+
+    let mut requires = vec![Vec::new(); stops.len()];
+    for (x, y) in before {
+        let position = |id: u32| stops.iter().position(|n| graph[*n].id == id).expect("Stop disappeared");
+        match (position(*x), position(*y)) {
+            (0, _) => {}
+            (_, 0) => panic!("{} can't be visited before the start", graph[stops[position(*x)]].name),
+            (x, y) => requires[y].push(x),
+        }
+    }
+
+    let costs = stops.par_iter().map(|from| {
+        let distances = algo::dijkstra(graph, *from, None, |e| e.weight().weight);
+        stops.iter().map(|to| distances.get(to).copied().unwrap_or(f32::INFINITY)).collect::<Vec<_>>()
+    }).collect::<Vec<_>>();
+
+    let order = if stops.len() - 1 <= EXACT_TARGETS { held_karp(&costs, &requires, round_trip) } else { approximate_tour(&costs, &requires, round_trip) };
+
+    let mut route = Vec::new();
+    let back = round_trip.then_some(0);
+    for w in std::iter::once(0).chain(order).chain(back).collect::<Vec<_>>().windows(2) {
+        let (from, to) = (stops[w[0]], stops[w[1]]);
+        let (_, path) = algo::astar(graph, from, |n| n == to, |e| e.weight().weight, |_| 0.0)
+            .expect("Target is not reachable from the start");
+        route.extend(path.into_iter().skip(1).map(|n| graph[n].id));
+    }
+    route
+}
+
+/// Exact cheapest tour from stop 0 through every other stop, open or with `closed` back to stop
+/// 0, by dynamic programming over subsets. `requires[i]` lists the stops that have to come before
+/// stop i, missing entries require nothing. Returns the stops after 0 in visiting order.
+fn held_karp(costs: &[Vec<f32>], requires: &[Vec<usize>], closed: bool) -> Vec<usize> {
+    let n = costs.len() - 1;
+    if n == 0 {
+        return Vec::new();
+    }
+
+    // best[mask][last]: cheapest cost visiting the stops in mask (bit i is stop i + 1), ending at last
+    let full = 1usize << n;
+    let required = (0..n).map(|i| requires.get(i + 1).map_or(0, |before| before.iter().map(|stop| 1usize << (stop - 1)).sum()))
+        .collect::<Vec<usize>>();
+    let mut best = vec![vec![(f32::INFINITY, usize::MAX); n]; full];
+    for last in 0..n {
+        if required[last] == 0 {
+            best[1 << last][last] = (costs[0][last + 1], usize::MAX);
+        }
+    }
+    for mask in 1..full {
+        for last in 0..n {
+            let (cost, _) = best[mask][last];
+            if mask & (1 << last) == 0 || !cost.is_finite() {
+                continue;
+            }
+            for next in 0..n {
+                if mask & (1 << next) != 0 || required[next] & !mask != 0 {
+                    continue;
+                }
+                let candidate = cost + costs[last + 1][next + 1];
+                let entry = &mut best[mask | (1 << next)][next];
+                if candidate < entry.0 {
+                    *entry = (candidate, last);
+                }
+            }
+        }
+    }
+
+    let mut mask = full - 1;
+    let total = |last: usize| best[mask][last].0 + if closed { costs[last + 1][0] } else { 0.0 };
+    let mut last = (0..n).min_by(|a, b| total(*a).total_cmp(&total(*b))).expect("No stops");
+    let mut order = Vec::new();
+    while last != usize::MAX {
+        order.push(last + 1);
+        let previous = best[mask][last].1;
+        mask &= !(1 << last);
+        last = previous;
+    }
+    order.reverse();
+    order
+}
+
+/// Greedy open tour from stop 0, always going to the cheapest unvisited stop whose required
+/// stops are all visited next.
+fn nearest_neighbour(costs: &[Vec<f32>], requires: &[Vec<usize>]) -> Vec<usize> {
+    let mut remaining = (1..costs.len()).collect::<Vec<_>>();
+    let mut current = 0;
+    let mut order = Vec::new();
+    let ready = |stop: usize, order: &[usize]| requires.get(stop).is_none_or(|before| before.iter().all(|b| order.contains(b)));
+    while let Some(position) = (0..remaining.len())
+        .filter(|i| ready(remaining[*i], &order))
+        .min_by(|a, b| costs[current][remaining[*a]].total_cmp(&costs[current][remaining[*b]])) {
+        current = remaining.remove(position);
+        order.push(current);
+    }
+    order
+}
+
+/// Open tour from stop 0 through the other stops in preorder of a minimum spanning tree. Costs
+/// between stops are shortest paths, which obey the triangle inequality, so the tour costs at
+/// most twice the spanning tree and so at most twice the optimum, even closed back to stop 0.
+fn mst_tour(costs: &[Vec<f32>]) -> Vec<usize> {
+    // Prim's algorithm from stop 0, tracking the cheapest link into the tree and its parent
+    let n = costs.len();
+    let mut in_tree = vec![false; n];
+    let mut link = vec![(f32::INFINITY, 0); n];
+    let mut children = vec![Vec::new(); n];
+    link[0].0 = 0.0;
+    while let Some(next) = (0..n).filter(|i| !in_tree[*i]).min_by(|a, b| link[*a].0.total_cmp(&link[*b].0)) {
+        in_tree[next] = true;
+        if next != 0 {
+            children[link[next].1].push(next);
+        }
+        for other in 0..n {
+            if !in_tree[other] && costs[next][other] < link[other].0 {
+                link[other] = (costs[next][other], next);
+            }
+        }
+    }
+
+    let mut order = Vec::new();
+    let mut stack = vec![0];
+    while let Some(stop) = stack.pop() {
+        if stop != 0 {
+            order.push(stop);
+        }
+        stack.extend(children[stop].iter().rev());
+    }
+    order
+}
+
+fn tour_cost(costs: &[Vec<f32>], order: &[usize], closed: bool) -> f32 {
+    std::iter::once(0).chain(order.iter().copied()).chain(closed.then_some(0)).collect::<Vec<_>>().windows(2).map(|w| costs[w[0]][w[1]]).sum()
+}
+
+/// Improves a tour from stop 0 by reversing any stretch of it that makes the tour cheaper, until
+/// no reversal helps. Reversals that would break the precedence in `requires` are skipped.
+fn two_opt(costs: &[Vec<f32>], mut order: Vec<usize>, requires: &[Vec<usize>], closed: bool) -> Vec<usize> {
+    let respects = |order: &[usize]| order.iter().enumerate().all(|(i, stop)| {
+        requires.get(*stop).is_none_or(|before| before.iter().all(|b| order[..i].contains(b)))
+    });
+
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in 0..order.len() {
+            for j in i + 1..order.len() {
+                let previous = if i == 0 { 0 } else { order[i - 1] };
+                let mut delta = costs[previous][order[j]] - costs[previous][order[i]];
+                if let Some(next) = order.get(j + 1).copied().or(closed.then_some(0)) {
+                    delta += costs[order[i]][next] - costs[order[j]][next];
+                }
+                if delta < -1e-3 {
+                    order[i..=j].reverse();
+                    if respects(&order) {
+                        improved = true;
+                    } else {
+                        order[i..=j].reverse();
+                    }
+                }
+            }
+        }
+    }
+    order
+}
+
+/// The cheaper of the nearest neighbour and spanning tree tours, so never worse than twice the
+/// optimum, then shortened with 2-opt. The spanning tree tour can't follow precedence, so with
+/// any only nearest neighbour is used.
+fn approximate_tour(costs: &[Vec<f32>], requires: &[Vec<usize>], closed: bool) -> Vec<usize> {
+    let greedy = nearest_neighbour(costs, requires);
+    if requires.iter().any(|before| !before.is_empty()) {
+        return two_opt(costs, greedy, requires, closed);
+    }
+    let tree = mst_tour(costs);
+    let cheaper = if tour_cost(costs, &tree, closed) < tour_cost(costs, &greedy, closed) { tree } else { greedy };
+    two_opt(costs, cheaper, requires, closed)
+}
+
+/// Drops the trailing backtrack that only revisits systems, then travels the shortest way to the
+/// end system.
+pub fn end_route_at(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32], end_id: u32) -> Vec<u32> {
+    let mut visited = HashSet::<u32>::new();
+    visited.insert(start_id);
+
+    let mut last_new = 0;
+    for (position, id) in route.iter().enumerate() {
+        if visited.insert(*id) {
+            last_new = position + 1;
+        }
+    }
+
+    let mut result = route[..last_new].to_vec();
+
+    let current_index = node_index_of(graph, result.last().copied().unwrap_or(start_id)).expect("Route node disappeared");
+    let end_index = node_index_of(graph, end_id).expect("End system is not in the region");
+
+    let heuristic = Heuristic::new(graph, end_index);
+    let final_leg = algo::astar(
+        graph,
+        current_index,
+        |n| n == end_index,
+        |e| e.weight().weight,
+        |n| heuristic.estimate(&graph[n]),
+    ).expect("Cannot reach end system").1;
+
+    result.extend(final_leg.into_iter().skip(1).map(|n| graph[n].id));
+    result
+}
+
+/// A closed walk from the start through every system in its region with as few jumps as it can
+/// find: the systems are ordered as a waypoint tour, joined by shortest paths, closed back at the
+/// start and shortened with `optimize_route`. Transit-only systems are passed through when on the
+/// way but never visited for their own sake. With `end` the walk finishes there instead, after the
+/// last new system.
+pub fn cover_route(graph: &Graph<System, Connection, Undirected>, start_id: u32, transit_only: &HashSet<u32>, end: Option<u32>) -> Vec<u32> {
+    let region = region_of(graph, start_id);
+    let targets = region.node_weights().map(|n| n.id).filter(|id| *id != start_id && !transit_only.contains(id)).collect::<Vec<_>>();
+    let route = visit_targets(&region, start_id, &targets, &[], true);
+    let route = match end {
+        Some(end) => end_route_at(&region, start_id, &route, end),
+        None => route,
+    };
+    optimize_route(&region, start_id, &route, usize::MAX).0
+}
+
+/// Most odd-degree systems paired up exactly for the postman route, by dynamic programming over
+/// subsets. More are paired greedily.
+pub const EXACT_MATCHING: usize = 20;
+
+/// Cheapest way to pair up the given points, as index pairs. Exact up to EXACT_MATCHING points,
+/// otherwise the closest remaining pair is taken each time.
+fn min_matching(costs: &[Vec<f32>]) -> Vec<(usize, usize)> {
+    let n = costs.len();
+    if n > EXACT_MATCHING {
+        let mut remaining = (0..n).collect::<Vec<_>>();
+        let mut pairs = Vec::new();
+        while remaining.len() > 1 {
+            let (a, b) = (0..remaining.len())
+                .flat_map(|a| (a + 1..remaining.len()).map(move |b| (a, b)))
+                .min_by(|x, y| costs[remaining[x.0]][remaining[x.1]].total_cmp(&costs[remaining[y.0]][remaining[y.1]]))
+                .expect("At least two points remain");
+            pairs.push((remaining[a], remaining[b]));
+            remaining.remove(b);
+            remaining.remove(a);
+        }
+        return pairs;
+    }
+
+    // best[mask]: cheapest pairing of the points in mask, always pairing its lowest point first
+    let full = 1usize << n;
+    let mut best = vec![(f32::INFINITY, 0, 0); full];
+    best[0].0 = 0.0;
+    for mask in (1..full).filter(|mask| mask.count_ones() % 2 == 0) {
+        let a = mask.trailing_zeros() as usize;
+        for b in (a + 1..n).filter(|b| mask & (1 << b) != 0) {
+            let cost = best[mask & !(1 << a) & !(1 << b)].0 + costs[a][b];
+            if cost < best[mask].0 {
+                best[mask] = (cost, a, b);
+            }
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let mut mask = full - 1;
+    while mask != 0 {
+        let (_, a, b) = best[mask];
+        pairs.push((a, b));
+        mask &= !(1 << a) & !(1 << b);
+    }
+    pairs
+}
+
+/// A closed walk from the start taking every connection in its region at least once. Systems
+/// with an odd number of connections are paired up as cheaply as possible by shortest path, the
+/// paths between each pair are taken twice, and the walk follows an Euler circuit. With `end` the
+/// walk finishes there instead: the start and end are left unpaired and the walk follows an Euler
+/// path between them. Returns the route and how many jumps repeat a connection.
+pub fn postman_route(graph: &Graph<System, Connection, Undirected>, start_id: u32, end: Option<u32>) -> (Vec<u32>, usize) {
+    let region = region_of(graph, start_id);
+    let start_index = node_index_of(&region, start_id).expect("Start node disappeared");
+    let end_index = end.map(|end| node_index_of(&region, end).expect("End system is not in the region")).filter(|end| *end != start_index);
+
+    // An open walk needs an odd number of connections at its ends, so they count as one more
+    let odd = region.node_indices().filter(|n| {
+        let ends = usize::from(Some(*n) == end_index || (*n == start_index && end_index.is_some()));
+        (region.edges(*n).count() + ends) % 2 == 1
+    }).collect::<Vec<_>>();
+    let costs = odd.par_iter().map(|from| {
+        let distances = algo::dijkstra(&region, *from, None, |e| e.weight().weight);
+        odd.iter().map(|to| distances.get(to).copied().unwrap_or(f32::INFINITY)).collect::<Vec<_>>()
+    }).collect::<Vec<_>>();
+
+    let mut edges = region.edge_references().map(|e| (e.source(), e.target())).collect::<Vec<_>>();
+    let original = edges.len();
+    for (a, b) in min_matching(&costs) {
+        let (_, path) = algo::astar(&region, odd[a], |n| n == odd[b], |e| e.weight().weight, |_| 0.0)
+            .expect("Region is connected");
+        edges.extend(path.windows(2).map(|w| (w[0], w[1])));
+    }
+
+    // Hierholzer's algorithm, every system now has an even number of connections
+    let mut incident = vec![Vec::new(); region.node_count()];
+    for (i, (a, b)) in edges.iter().enumerate() {
+        incident[a.index()].push((i, *b));
+        incident[b.index()].push((i, *a));
+    }
+    let mut used = vec![false; edges.len()];
+    let mut stack = vec![start_index];
+    let mut circuit = Vec::new();
+    while let Some(&node) = stack.last() {
+        match incident[node.index()].pop() {
+            Some((edge, next)) if !used[edge] => {
+                used[edge] = true;
+                stack.push(next);
+            }
+            Some(_) => {}
+            None => circuit.push(stack.pop().expect("Stack is not empty")),
+        }
+    }
+    circuit.reverse();
+
+    (circuit.into_iter().skip(1).map(|n| region[n].id).collect(), edges.len() - original)
+}
+
+/// Jumps into a system the route has already visited, the start counting as visited.
+pub fn revisits(route: &[u32], start_id: u32) -> usize {
+    let mut visited = HashSet::from([start_id]);
+    route.iter().filter(|id| !visited.insert(**id)).count()
+}
+
+/// Shortens a route by local search, keeping where it starts and ends and every system it visits.
+/// Each pass looks for a stretch of the route that a cheaper path between its ends can replace,
+/// because every system inside it is also visited elsewhere or on the cheaper path, which cuts out
+/// needless backtracking. Cost is the connection weight, so detours made to avoid heavily
+/// weighted connections stay. Stops after `passes` passes or once a pass finds nothing, and
+/// returns the shortened route with how many stretches were replaced.
+pub fn optimize_route(graph: &Graph<System, Connection, Undirected>, start_id: u32, route: &[u32], passes: usize) -> (Vec<u32>, usize) {
+    let index = graph.node_references().map(|(i, n)| (n.id, i)).collect::<HashMap<_, _>>();
+    let mut walk = std::iter::once(start_id).chain(route.iter().copied())
+        .map(|id| index[&id])
+        .collect::<Vec<_>>();
+    let costs = graph.node_indices().collect::<Vec<_>>().par_iter()
+        .map(|n| (*n, algo::dijkstra(graph, *n, None, |e| e.weight().weight)))
+        .collect::<HashMap<_, _>>();
+    // Cost of the walk up to each position, redone whenever a stretch is replaced
+    let walk_costs = |walk: &[NodeIndex]| std::iter::once(0.0).chain(walk.windows(2).scan(0.0, |total, w| {
+        *total += graph.edges_connecting(w[0], w[1]).map(|e| e.weight().weight).fold(f32::INFINITY, f32::min);
+        Some(*total)
+    })).collect::<Vec<f32>>();
+    let mut walked = walk_costs(&walk);
+    let mut count = HashMap::<NodeIndex, usize>::new();
+    for n in &walk {
+        *count.entry(*n).or_default() += 1;
+    }
+
+    let mut replaced = 0;
+    for _ in 0..passes {
+        let mut improved = false;
+        let mut i = 0;
+        while i + 2 < walk.len() {
+            // Grow the stretch after i, tracking the systems visited only inside it
+            let mut inside = HashMap::<NodeIndex, usize>::new();
+            let mut only_inside = Vec::new();
+            let mut shortcut = None;
+            for j in i + 2..walk.len() {
+                let added = walk[j - 1];
+                let seen = inside.entry(added).or_default();
+                *seen += 1;
+                if *seen == count[&added] {
+                    only_inside.push(added);
+                }
+                if costs[&walk[i]].get(&walk[j]).is_none_or(|cost| *cost >= walked[j] - walked[i] - 1e-3) {
+                    continue;
+                }
+                let path = algo::astar(graph, walk[i], |n| n == walk[j], |e| e.weight().weight, |_| 0.0)
+                    .expect("Route systems are connected").1;
+                if only_inside.iter().all(|n| path.contains(n)) {
+                    shortcut = Some((j, path));
+                    break;
+                }
+            }
+
+            match shortcut {
+                Some((j, path)) => {
+                    for n in &walk[i..=j] {
+                        *count.get_mut(n).expect("Counted every system") -= 1;
+                    }
+                    for n in &path {
+                        *count.entry(*n).or_default() += 1;
+                    }
+                    walk.splice(i..=j, path);
+                    walked = walk_costs(&walk);
+                    replaced += 1;
+                    improved = true;
+                }
+                None => i += 1,
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    (walk.into_iter().skip(1).map(|n| graph[n].id).collect(), replaced)
+}
+
+/// For tree regions, where every trip out from the start comes back the way it went. Moves the
+/// trip with the longest walk back to the end of the route and drops that walk back, so the route
+/// finishes at its furthest system. Routes that don't end at the start are returned unchanged.
+pub fn drop_tree_return(route: &[u32], start_id: u32) -> Vec<u32> {
+    if route.last() != Some(&start_id) {
+        return route.to_vec();
+    }
+
+    let walk_back = |trip: &[u32]| {
+        let mut seen = HashSet::from([start_id]);
+        let last_new = trip.iter().enumerate()
+            .filter(|(_, id)| seen.insert(**id))
+            .last()
+            .map_or(0, |(position, _)| position + 1);
+        trip.len() - last_new
+    };
+
+    let mut trips = route.split_inclusive(|id| *id == start_id).collect::<Vec<_>>();
+    let furthest = (0..trips.len()).max_by_key(|i| walk_back(trips[*i])).expect("Route is not empty");
+    let last = trips.remove(furthest);
+    trips.push(&last[..last.len() - walk_back(last)]);
+    trips.concat()
+}
+
+/// Neighbour lists keyed by system ID, matching the map's neighbours field.
+pub fn adjacency(graph: &Graph<System, Connection, Undirected>) -> BTreeMap<u32, Vec<u32>> {
+    graph.node_references().map(|(i, system)| {
+        let mut neighbours = graph.neighbors(i).map(|n| graph[n].id).collect::<Vec<_>>();
+        neighbours.sort();
+        (system.id, neighbours)
+    }).collect()
+}
+
+/// Splits the route into consecutive legs of at most `leg_size` jumps. Each leg ends within the
+/// last fifth of its allowance at the best rest point: highsec first, then the fewest gates.
+/// Without security data every system counts as highsec, so only the gates decide.
+pub fn split_legs(graph: &Graph<System, Connection, Undirected>, route: &[u32], leg_size: usize) -> Vec<std::ops::Range<usize>> {
+    let leg_size = leg_size.max(1);
+    let rest_score = |id: u32| {
+        let index = node_index_of(graph, id).expect("Route node disappeared");
+        (is_highsec(&graph[index]), std::cmp::Reverse(graph.neighbors(index).count()))
+    };
+
+    let mut legs = Vec::new();
+    let mut start = 0;
+    while start < route.len() {
+        let limit = (start + leg_size).min(route.len());
+        let end = if limit == route.len() {
+            limit
+        } else {
+            let window = (limit - leg_size / 5).max(start + 1)..=limit;
+            window.max_by_key(|end| (rest_score(route[end - 1]), *end)).unwrap_or(limit)
+        };
+        legs.push(start..end);
+        start = end;
+    }
+    legs
+}
+
+pub fn compact_route(route: &[u32]) -> String {
+    route.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// Numbered "-> Name" lines for each jump, with consecutive repeats of a system collapsed.
+pub fn breadcrumbs(route: &[u32], names: &HashMap<u32, String>) -> Vec<String> {
+    let mut route = route.to_vec();
+    route.dedup();
+    route.iter().enumerate()
+        .map(|(i, id)| format!("{}. -> {}", i + 1, names.get(id).map_or("?", String::as_str)))
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SegmentStats {
+    pub jumps: usize,
+    /// Jumps to systems the route hadn't visited before
+    pub new_systems: usize,
+}
+
+impl SegmentStats {
+    /// Share of the segment's jumps spent on systems already visited.
+    pub fn backtrack_ratio(&self) -> f64 {
+        if self.jumps == 0 { 0.0 } else { 1.0 - self.new_systems as f64 / self.jumps as f64 }
+    }
+}
+
+/// Splits the route into trips out from the start and back, counting the new systems in each.
+pub fn segment_stats(route: &[u32], start_id: u32) -> Vec<SegmentStats> {
+    let mut visited = HashSet::from([start_id]);
+    route.split_inclusive(|id| *id == start_id).map(|segment| SegmentStats {
+        jumps: segment.len(),
+        new_systems: segment.iter().filter(|id| visited.insert(**id)).count(),
+    }).collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct Step<'a> {
+    pub position: usize,
+    pub id: u32,
+    pub name: &'a str,
+    /// Trips out from the start, counting up each time the route comes back to it
+    pub segment: usize,
+}
+
+pub fn steps<'a>(route: &[u32], start_id: u32, names: &'a HashMap<u32, String>) -> Vec<Step<'a>> {
+    let mut segment = 0;
+    route.iter().enumerate().map(|(i, id)| {
+        let step = Step {
+            position: i + 1,
+            id: *id,
+            name: names.get(id).map_or("?", String::as_str),
+            segment,
+        };
+        if *id == start_id {
+            segment += 1;
+        }
+        step
+    }).collect()
+}
+
+/// Markdown task list with one item per jump, starting a new region heading whenever the route
+/// crosses into another region unless `flat`.
+pub fn checklist(graph: &Graph<System, Connection, Undirected>, route: &[u32], names: &HashMap<u32, String>, flat: bool) -> Vec<String> {
+    if flat {
+        return route.iter().enumerate()
+            .map(|(i, id)| format!("- [ ] {}. {}", i + 1, names.get(id).map_or("?", String::as_str)))
+            .collect();
+    }
+
+    let regions = graph.node_weights().map(|n| (n.id, n.region_id)).collect::<HashMap<_, _>>();
+    let mut lines = Vec::new();
+    let mut current_region = None;
+    for (i, id) in route.iter().enumerate() {
+        let region = regions.get(id).copied();
+        if region != current_region {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            if let Some(region) = region {
+                lines.push(format!("## Region {}", region));
+                lines.push(String::new());
+            }
+            current_region = region;
+        }
+        lines.push(format!("- [ ] {}. {}", i + 1, names.get(id).map_or("?", String::as_str)));
+    }
+    lines
+}
+
+/// Each system once, in the order the route first reaches it, with the start at 0.
+pub fn first_visit_order(route: &[u32], start_id: u32) -> Vec<u32> {
+    let mut seen = HashSet::new();
+    std::iter::once(start_id).chain(route.iter().copied()).filter(|id| seen.insert(*id)).collect()
+}
+
+/// Turn points of the route: each destination is the furthest system the autopilot would reach
+/// along the route from the previous one, because the route up to it is the only shortest path.
+pub fn destinations(graph: &Graph<System, Connection, Undirected>, route: &[u32], start_id: u32) -> Vec<u32> {
+    let indices = graph.node_references().map(|(i, n)| (n.id, i)).collect::<HashMap<_, _>>();
+    let route = std::iter::once(start_id).chain(route.iter().copied()).collect::<Vec<_>>();
+
+    let mut stops = Vec::new();
+    let mut from = 0;
+    while from + 1 < route.len() {
+        // Jumps and number of shortest paths from the current stop to every system
+        let source = indices[&route[from]];
+        let mut distances = HashMap::from([(source, 0usize)]);
+        let mut paths = HashMap::from([(source, 1usize)]);
+        let mut queue = VecDeque::from([source]);
+        while let Some(n) = queue.pop_front() {
+            for next in graph.neighbors(n) {
+                if !distances.contains_key(&next) {
+                    distances.insert(next, distances[&n] + 1);
+                    queue.push_back(next);
+                }
+                if distances[&next] == distances[&n] + 1 {
+                    let through = paths[&n];
+                    let count = paths.entry(next).or_insert(0);
+                    *count = count.saturating_add(through);
+                }
+            }
+        }
+
+        let mut to = from + 1;
+        while to + 1 < route.len() {
+            let next = indices[&route[to + 1]];
+            if distances[&next] != to + 1 - from || paths[&next] != 1 {
+                break;
+            }
+            to += 1;
+        }
+        stops.push(route[to]);
+        from = to;
+    }
+    stops
+}
+
+pub const DOTLAN_ROUTE_URL: &str = "https://evemaps.dotlan.net/route/";
+
+/// Dotlan route URL visiting the systems in order. Dotlan separates waypoints with `:` and
+/// writes spaces as `_`, anything else outside the unreserved URL characters is percent encoded.
+pub fn dotlan_url<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+    let waypoints = names.into_iter().map(|name| {
+        name.replace(' ', "_").bytes().map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        }).collect::<String>()
+    }).collect::<Vec<_>>();
+    format!("{}{}", DOTLAN_ROUTE_URL, waypoints.join(":"))
+}
+
+pub fn parse_compact_route(route: &str) -> Result<Vec<u32>, std::num::ParseIntError> {
+    route.split(',').map(str::trim).filter(|id| !id.is_empty()).map(str::parse).collect()
+}
+
+pub fn sort_tuple<T>(v: (T, T)) -> (T, T)
+where  T: PartialOrd {
+    if v.0 < v.1 {
+        (v.0, v.1)
+    } else {
+        (v.1, v.0)
+    }
+}
+
+/// Map field names that differ between community exports.
+#[derive(Debug, Clone)]
+pub struct FieldNames {
+    pub id: String,
+    pub name: String,
+    pub neighbours: String,
+}
+
+impl Default for FieldNames {
+    fn default() -> Self {
+        FieldNames {
+            id: "solarSystemID".to_owned(),
+            name: "solarSystemName".to_owned(),
+            neighbours: "neighbours".to_owned(),
+        }
+    }
+}
+
+pub fn load_map(path: &str, fields: &FieldNames) -> Result<HashMap<u32, SolarSystem>, RouteError> {
+    // Catch these before serde, which reports them as confusing parse errors
+    let metadata = std::fs::metadata(path).map_err(|source| RouteError::Io { path: path.to_owned(), source })?;
+    let invalid = |message: &str| Err(RouteError::Parse { path: path.to_owned(), message: message.to_owned() });
+    if metadata.is_dir() {
+        return invalid("it is a directory, expected a JSON file");
+    }
+    if metadata.len() == 0 {
+        return invalid("the file is empty");
+    }
+
+    let defaults = FieldNames::default();
+    if fields.id == defaults.id && fields.name == defaults.name && fields.neighbours == defaults.neighbours {
+        return read_json(path);
+    }
+
+    // Rename the mapped fields to the standard schema before deserializing each system
+    let raw: HashMap<u32, serde_json::Map<String, serde_json::Value>> = read_json(path)?;
+    raw.into_iter().map(|(key, mut system)| {
+        for (from, to) in [(&fields.id, &defaults.id), (&fields.name, &defaults.name), (&fields.neighbours, &defaults.neighbours)] {
+            if let Some(value) = system.remove(from) {
+                system.insert(to.clone(), value);
+            }
+        }
+        let system = serde_json::from_value(serde_json::Value::Object(system))
+            .map_err(|e| RouteError::Parse { path: path.to_owned(), message: format!("system {}: {}", key, e) })?;
+        Ok((key, system))
+    }).collect()
+}
+
+/// Where the graph comes from and the edits made to it before routing.
+#[derive(Debug, Clone, Default)]
+pub struct GraphSource {
+    pub map: String,
+    pub fields: FieldNames,
+    /// Graph file loaded instead of the map while it exists, and saved with the edits
+    pub cache: Option<String>,
+    pub check_symmetry: bool,
+    /// Weight overrides, applied after the cache is saved so it always holds default weights
+    pub weights: HashMap<(u32, u32), f32>,
+    /// Wormhole connections to add, by system name or ID
+    pub add_edges: Vec<(String, String)>,
+    /// Connections to remove, by system name or ID
+    pub remove_edges: Vec<(String, String)>,
+}
+
+impl GraphSource {
+    /// Loads the cache if it exists or builds the graph from the map, makes the edits, saves the
+    /// cache and applies the weights.
+    pub fn load(&self, warnings: &mut Vec<String>) -> Result<Graph<System, Connection, Undirected>, RouteError> {
+        let mut graph = match &self.cache {
+            Some(path) if Path::new(path).exists() => {
+                if self.check_symmetry {
+                    return Err(RouteError::InvalidArgument(format!("--check-symmetry reads the map, which isn't loaded while {} exists", path)));
+                }
+                load_graph(path)?
+            }
+            _ => build_graph(&load_map(&self.map, &self.fields)?, &BuildOptions {
+                check_symmetry: self.check_symmetry,
+                ..Default::default()
+            }, warnings),
+        };
+
+        for (a, b) in &self.add_edges {
+            let (a_id, b_id) = (graph[require_system(&graph, a)?].id, graph[require_system(&graph, b)?].id);
+            if !add_connection(&mut graph, a_id, b_id, ConnectionKind::Wormhole) {
+                warnings.push(format!("{} and {} are already connected", a, b));
+            }
+        }
+
+        for (a, b) in &self.remove_edges {
+            let (a_id, b_id) = (graph[require_system(&graph, a)?].id, graph[require_system(&graph, b)?].id);
+            if !remove_connection(&mut graph, a_id, b_id) {
+                warnings.push(format!("{} and {} are not connected", a, b));
+            }
+        }
+
+        if let Some(path) = &self.cache {
+            save_graph(&graph, path)?;
+        }
+
+        apply_weights(&mut graph, &self.weights, warnings);
+        Ok(graph)
+    }
+}
+
+/// Data problems in the map, and with `esi` every difference from the live universe.
+pub fn verify_map(data: &HashMap<u32, SolarSystem>, esi: bool) -> Vec<String> {
+    let mut problems = Vec::<String>::new();
+    build_graph(data, &BuildOptions {
+        check_symmetry: true,
+        ..Default::default()
+    }, &mut problems);
+
+    if esi {
+        let mut systems = data.values().collect::<Vec<_>>();
+        systems.sort_by_key(|ss| ss.solarSystemID);
+
+        let differences = systems.par_iter().map(|ss| {
+            match esi::compare_system(ss.solarSystemID, &ss.solarSystemName, &ss.neighbours) {
+                Ok(differences) => differences,
+                Err(e) => vec![format!("{}: ESI request failed: {}", ss.solarSystemName, e)],
+            }
+        }).collect::<Vec<_>>();
+
+        problems.extend(differences.into_iter().flatten());
+    }
+
+    problems
+}
+
+/// Downloads the map from ESI into `path` in the map file format, unless the copy there is still
+/// current. `force` downloads it regardless.
+pub fn fetch_map(path: &str, force: bool) -> Result<Fetched, RouteError> {
+    esi::fetch_map(path, force).map_err(|e| RouteError::Fetch(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_graph(count: u32, edges: &[(u32, u32)]) -> Graph<System, Connection, Undirected> {
+        let mut graph = Graph::<System, Connection, Undirected>::new_undirected();
+        let nodes = (0..count).map(|id| graph.add_node(System {
+            id,
+            name: format!("S{}", id),
+            region_id: 0,
+            region_name: None,
+            constellation_id: None,
+            constellation_name: None,
+            security: None,
+            center: None,
+            has_station: false,
+        })).collect::<Vec<_>>();
+        for (a, b) in edges {
+            graph.add_edge(nodes[*a as usize], nodes[*b as usize], Connection::new(ConnectionKind::Gate));
+        }
+        graph
+    }
+
+    fn solar_system(id: u32, name: &str, neighbours: &[u32]) -> SolarSystem {
+        SolarSystem {
+            solarSystemID: id,
+            solarSystemName: name.to_owned(),
+            regionID: 0,
+            regionName: None,
+            constellationID: None,
+            constellationName: None,
+            security: None,
+            center: None,
+            neighbours: neighbours.to_vec(),
+            stations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn build_graph_keeps_first_duplicate_id() {
+        let data = HashMap::from([
+            (1, solar_system(1, "A", &[2])),
+            (2, solar_system(2, "B", &[1, 3])),
+            (3, solar_system(3, "C", &[2])),
+            (4, solar_system(2, "B copy", &[1])),
+        ]);
+        let mut warnings = Vec::new();
+
+        let graph = build_graph(&data, &BuildOptions::default(), &mut warnings);
+
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.node_weights().filter(|n| n.id == 2).map(|n| n.name.as_str()).collect::<Vec<_>>(), ["B"]);
+        assert!(graph.node_indices().all(|i| graph.neighbors(i).count() > 0));
+        let mut edges = graph.edge_references().map(|e| sort_tuple((graph[e.source()].id, graph[e.target()].id))).collect::<Vec<_>>();
+        edges.sort();
+        assert_eq!(edges, [(1, 2), (2, 3)]);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn filter_nodes_keeps_only_retained_nodes() {
+        let graph = test_graph(6, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 0), (1, 4)]);
+        let retained = [0, 1, 3, 4];
+
+        let filtered = filter_nodes(&graph, |_, n| retained.contains(&n.id));
+
+        let ids = filtered.node_weights().map(|n| n.id).collect::<Vec<_>>();
+        assert_eq!(ids, retained);
+    }
+
+    #[test]
+    fn filter_nodes_keeps_only_edges_between_retained_nodes() {
+        let graph = test_graph(6, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 0), (1, 4)]);
+        let retained = [0, 1, 3, 4];
+
+        let filtered = filter_nodes(&graph, |_, n| retained.contains(&n.id));
+
+        let mut edges = filtered.edge_references()
+            .map(|e| sort_tuple((filtered[e.source()].id, filtered[e.target()].id)))
+            .collect::<Vec<_>>();
+        edges.sort();
+        assert_eq!(edges, vec![(0, 1), (1, 4), (3, 4)]);
+    }
+
+    #[test]
+    fn filter_nodes_renumbers_indices() {
+        // Indices are compacted, so callers must look systems up by ID after filtering
+        let graph = test_graph(4, &[(0, 1), (1, 2), (2, 3)]);
+
+        let filtered = filter_nodes(&graph, |_, n| n.id != 1);
+
+        assert_eq!(filtered.node_count(), 3);
+        assert_eq!(filtered[NodeIndex::new(1)].id, 2);
+        assert_eq!(node_index_of(&filtered, 3), Some(NodeIndex::new(2)));
+        assert_eq!(node_index_of(&filtered, 1), None);
+    }
+
+    #[test]
+    fn trivial_route_matches_search_on_pipe() {
+        // 0 - 1 - 2 - 3 - 4 - 5, starting off-centre at 2
+        let graph = test_graph(6, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5)]);
+
+        let trivial = trivial_route(&graph, 2).expect("Pipe not detected");
+        assert_eq!(trivial, vec![3, 4, 5, 4, 3, 2, 1, 0, 1, 2]);
+        assert_eq!(trivial, search_longest_paths(graph, 2, &SearchOptions::default(), &SearchGuard::default()));
+    }
+
+    #[test]
+    fn trivial_route_matches_search_on_cycle() {
+        let graph = test_graph(5, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 0)]);
+
+        let trivial = trivial_route(&graph, 0).expect("Cycle not detected");
+        let searched = search_longest_paths(graph, 0, &SearchOptions::default(), &SearchGuard::default());
+
+        // Both directions around the cycle are equally long
+        let mirrored = searched[..searched.len() - 1].iter().rev().copied().chain([0]).collect::<Vec<_>>();
+        assert!(trivial == searched || trivial == mirrored);
+        assert_eq!(trivial.len(), 5);
+    }
+
+    #[test]
+    fn euclidean_heuristic_is_admissible() {
+        let mut graph = test_graph(5, &[]);
+        let centers = [[0.0, 0.0, 0.0], [3.0, 4.0, 0.0], [6.0, 0.0, 0.0], [3.0, -1.0, 0.0], [10.0, 10.0, 10.0]];
+        for (n, center) in graph.node_weights_mut().zip(centers) {
+            n.center = Some(center);
+        }
+        let n = graph.node_indices().collect::<Vec<_>>();
+        graph.add_edge(n[0], n[1], Connection { weight: 2.0, ..Connection::new(ConnectionKind::Gate) });
+        graph.add_edge(n[1], n[2], Connection { weight: 1.0, ..Connection::new(ConnectionKind::Gate) });
+        graph.add_edge(n[0], n[3], Connection { weight: 5.0, ..Connection::new(ConnectionKind::Gate) });
+        graph.add_edge(n[3], n[2], Connection { weight: 0.5, ..Connection::new(ConnectionKind::Gate) });
+        graph.add_edge(n[2], n[4], Connection { weight: 3.0, ..Connection::new(ConnectionKind::Gate) });
+
+        for target in graph.node_indices() {
+            let heuristic = Heuristic::new(&graph, target);
+            assert!(matches!(heuristic, Heuristic::Euclidean { .. }));
+
+            let costs = algo::dijkstra(&graph, target, None, |e| e.weight().weight);
+            for (node, cost) in costs {
+                assert!(heuristic.estimate(&graph[node]) <= cost + 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn release_start_stops_looping_through_the_start() {
+        // Start 0 sits on the cycle 0-1-2-3, with a pipe 2-4-5 hanging off the far side
+        let graph = test_graph(6, &[(0, 1), (1, 2), (2, 3), (3, 0), (2, 4), (4, 5)]);
+        let options = SearchOptions {
+            release_start: true,
+            ..Default::default()
+        };
+
+        let route = find_longest_paths(graph.clone(), 0, &options);
+
+        assert_eq!(route.iter().filter(|id| **id == 0).count(), 1);
+        assert_eq!(route.last(), Some(&0));
+        assert_eq!(route.iter().collect::<HashSet<_>>().len(), 6);
+
+        let looping = find_longest_paths(graph, 0, &SearchOptions::default());
+        assert!(looping.iter().filter(|id| **id == 0).count() > 1);
+    }
+
+    #[test]
+    fn beam_search_covers_regions_too_big_to_enumerate() {
+        // A 15 x 15 grid has far too many simple paths to enumerate from a corner
+        let side = 15;
+        let edges = (0..side * side)
+            .flat_map(|i| [(i % side + 1 < side).then_some((i, i + 1)), (i + side < side * side).then_some((i, i + side))])
+            .flatten()
+            .collect::<Vec<_>>();
+        let graph = test_graph(side * side, &edges);
+
+        let route = find_longest_paths(graph, 0, &SearchOptions::default());
+
+        assert_eq!(route.iter().collect::<HashSet<_>>().len(), (side * side) as usize);
+        assert_eq!(route.last(), Some(&0));
+    }
+
+    #[test]
+    fn beam_search_ranks_paths_with_the_scorer() {
+        let side = 15;
+        let edges = (0..side * side)
+            .flat_map(|i| [(i % side + 1 < side).then_some((i, i + 1)), (i + side < side * side).then_some((i, i + side))])
+            .flatten()
+            .collect::<Vec<_>>();
+        let graph = test_graph(side * side, &edges);
+
+        // Whichever way the plain search leaves the corner, make leaving the other way worth more
+        let plain = find_longest_paths(graph.clone(), 0, &SearchOptions::default());
+        let other = if plain[0] == 1 { side } else { 1 };
+        let options = SearchOptions {
+            scorer: Some(Arc::new(move |path, graph| {
+                let bonus = if path.get(1).is_some_and(|n| graph[*n].id == other) { 1_000_000 } else { 0 };
+                path.len() as i64 * 1000 + bonus
+            })),
+            ..Default::default()
+        };
+
+        let route = find_longest_paths(graph, 0, &options);
+
+        assert_eq!(route[0], other);
+        assert_eq!(route.iter().collect::<HashSet<_>>().len(), (side * side) as usize);
+    }
+
+    #[test]
+    fn reroute_keeps_the_route_before_a_removed_connection() {
+        // Around the cycle 0-1-2-3-4-5 with a spur 6 off 3
+        let mut graph = test_graph(7, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 0), (3, 6)]);
+        let previous = find_longest_paths(graph.clone(), 0, &SearchOptions::default());
+        let (a, b) = (previous[1], previous[2]);
+        assert!(remove_connection(&mut graph, a, b));
+
+        let route = reroute(&graph, 0, &previous, (a, b), &SearchOptions::default()).unwrap();
+
+        assert_eq!(route[..2], previous[..2]);
+        assert_eq!(route.iter().collect::<HashSet<_>>().len(), 7);
+        assert_eq!(route.last(), Some(&0));
+        let jumps = std::iter::once(&0).chain(&route).collect::<Vec<_>>();
+        let connected = |x: u32, y: u32| graph.find_edge(node_index_of(&graph, x).unwrap(), node_index_of(&graph, y).unwrap()).is_some();
+        assert!(jumps.windows(2).all(|w| connected(*w[0], *w[1])));
+        assert_eq!(reroute(&graph, 0, &route, (a, b), &SearchOptions::default()).unwrap(), route);
+    }
+
+    #[test]
+    fn graph_cache_keeps_default_weights() {
+        let dir = std::env::temp_dir().join(format!("frontier_route_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let map = dir.join("map.json");
+        std::fs::write(&map, serde_json::json!({
+            "1": {"solarSystemID": 1, "solarSystemName": "A", "regionID": 1, "neighbours": [2], "stations": []},
+            "2": {"solarSystemID": 2, "solarSystemName": "B", "regionID": 1, "neighbours": [1], "stations": []},
+        }).to_string()).unwrap();
+        let weight = |graph: &Graph<System, Connection, Undirected>| graph.edge_weights().next().unwrap().weight;
+
+        let weighted = GraphSource {
+            map: map.to_string_lossy().into_owned(),
+            cache: Some(dir.join("cache.json").to_string_lossy().into_owned()),
+            weights: HashMap::from([((1, 2), 7.5)]),
+            ..Default::default()
+        };
+        assert_eq!(weight(&weighted.load(&mut Vec::new()).unwrap()), 7.5);
+
+        // A later run without the weights loads the cache
+        let unweighted = GraphSource { weights: HashMap::new(), ..weighted };
+        assert_eq!(weight(&unweighted.load(&mut Vec::new()).unwrap()), 1.0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn visited_systems_add_nothing_to_a_path() {
+        // From 0 a chain of visited systems 1-2-3-4 leads to 7, while 5-6 are both unvisited
+        let graph = test_graph(8, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 7), (0, 5), (5, 6)]);
+        let options = SearchOptions {
+            transit_only: HashSet::from([1, 2, 3, 4]),
+            ..Default::default()
+        };
+
+        let route = find_longest_paths(graph, 0, &options);
+
+        assert_eq!(route[..3], [5, 6, 5]);
+        assert!(route.contains(&7));
+    }
+
+    #[test]
+    fn optimize_route_cuts_needless_backtracking() {
+        // Around the cycle 0-1-2-3 with a wasted trip back to the start after 1
+        let graph = test_graph(4, &[(0, 1), (1, 2), (2, 3), (3, 0)]);
+
+        let (route, replaced) = optimize_route(&graph, 0, &[1, 0, 1, 2, 3, 0], 10);
+
+        assert_eq!(route, vec![1, 2, 3, 0]);
+        assert_eq!(replaced, 1);
+    }
+
+    #[test]
+    fn optimize_route_keeps_detours_around_heavy_connections() {
+        // The same cycle, but the connection from 3 back to the start costs more than going round
+        let mut graph = test_graph(4, &[(0, 1), (1, 2), (2, 3), (3, 0)]);
+        let heavy = graph.find_edge(node_index_of(&graph, 3).unwrap(), node_index_of(&graph, 0).unwrap()).unwrap();
+        graph[heavy].weight = 10.0;
+
+        let (route, replaced) = optimize_route(&graph, 0, &[1, 2, 3, 2, 1, 0], 10);
+
+        assert_eq!(route, vec![1, 2, 3, 2, 1, 0]);
+        assert_eq!(replaced, 0);
+    }
+
+    #[test]
+    fn postman_route_takes_every_connection() {
+        // The cycle 0-1-2-3 with the diagonal 0-2, so only 0 and 2 have an odd number of connections
+        let graph = test_graph(4, &[(0, 1), (1, 2), (2, 3), (3, 0), (0, 2)]);
+
+        let (route, repeated) = postman_route(&graph, 0, None);
+
+        assert_eq!(repeated, 1);
+        assert_eq!(route.len(), 6);
+        assert_eq!(route.last(), Some(&0));
+        let taken = std::iter::once(&0).chain(&route).collect::<Vec<_>>().windows(2)
+            .map(|w| sort_tuple((*w[0], *w[1])))
+            .collect::<HashSet<_>>();
+        assert_eq!(taken, HashSet::from([(0, 1), (1, 2), (2, 3), (0, 3), (0, 2)]));
+
+        // Finishing at 2 leaves nothing to repeat
+        let (route, repeated) = postman_route(&graph, 0, Some(2));
+        assert_eq!(repeated, 0);
+        assert_eq!(route.len(), 5);
+        assert_eq!(route.last(), Some(&2));
+    }
+
+    #[test]
+    fn max_start_revisits_caps_passes_through_the_start() {
+        // Start 0 joins three triangles, and each further pass through it covers another one
+        let graph = test_graph(7, &[(0, 1), (1, 2), (2, 0), (0, 3), (3, 4), (4, 0), (0, 5), (5, 6), (6, 0)]);
+        let passes = |route: &[u32]| route[..route.len() - 1].iter().filter(|id| **id == 0).count();
+
+        let looping = find_longest_paths(graph.clone(), 0, &SearchOptions::default());
+        assert_eq!(passes(&looping), 2);
+
+        for max in 0..2 {
+            let options = SearchOptions {
+                max_start_revisits: Some(max),
+                ..Default::default()
+            };
+            let route = find_longest_paths(graph.clone(), 0, &options);
+            assert_eq!(passes(&route), max);
+            assert_eq!(route.last(), Some(&0));
+            assert_eq!(route.len(), 3 * (max + 1));
+        }
+    }
+
+    #[test]
+    fn no_start_reentry_visits_leaves_before_returning() {
+        // Start 0 is a hub whose spokes 1, 2 and 3 are joined at 4; 2 and 3 also have leaves 5 and 6
+        let graph = test_graph(7, &[(0, 1), (0, 2), (0, 3), (1, 4), (2, 4), (3, 4), (2, 5), (3, 6)]);
+        let options = SearchOptions {
+            no_start_reentry: true,
+            ..Default::default()
+        };
+
+        let route = find_longest_paths(graph.clone(), 0, &options);
+
+        assert_eq!(route.iter().filter(|id| **id == 0).count(), 1);
+        assert_eq!(route.last(), Some(&0));
+        assert_eq!(route.iter().collect::<HashSet<_>>().len(), 7);
+
+        let bouncing = find_longest_paths(graph, 0, &SearchOptions::default());
+        assert!(bouncing.iter().filter(|id| **id == 0).count() > 1);
+    }
+
+    #[test]
+    fn parallel_stragglers_match_serial_order() {
+        // Spine 0-1-2-3-4 with branches 1-5-6 and 2-7 left over after the outbound loop
+        let graph = test_graph(8, &[(0, 1), (1, 2), (2, 3), (3, 4), (1, 5), (5, 6), (2, 7)]);
+
+        let route = find_longest_paths(graph.clone(), 0, &SearchOptions::default());
+        let single_thread = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap()
+            .install(|| find_longest_paths(graph, 0, &SearchOptions::default()));
+
+        assert_eq!(route, [1, 5, 6, 5, 1, 2, 7, 2, 3, 4, 3, 2, 1, 0]);
+        assert_eq!(route, single_thread);
+    }
+
+    #[test]
+    fn return_leg_follows_edge_weights() {
+        // 0 reaches 3 through either 1 or 2, and 3 leads on to the leaf 6
+        let weighted = |heavy: (u32, u32)| {
+            let mut graph = test_graph(7, &[(0, 1), (0, 2), (1, 3), (2, 3), (3, 4), (4, 5), (5, 6)]);
+            let edge = graph.find_edge(NodeIndex::new(heavy.0 as usize), NodeIndex::new(heavy.1 as usize)).unwrap();
+            graph[edge].weight = 10.0;
+            graph
+        };
+
+        let return_leg = |route: Vec<u32>| {
+            let leaf = route.iter().position(|id| *id == 6).unwrap();
+            route[leaf..leaf + 6].to_vec()
+        };
+
+        let route = find_longest_paths(weighted((0, 1)), 0, &SearchOptions::default());
+        assert_eq!(return_leg(route), [6, 5, 4, 3, 2, 0]);
+
+        let route = find_longest_paths(weighted((0, 2)), 0, &SearchOptions::default());
+        assert_eq!(return_leg(route), [6, 5, 4, 3, 1, 0]);
+    }
+
+    #[test]
+    fn straggler_seeding_is_bounded_on_dense_graphs() {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..20 {
+            let count = 10;
+            let mut edges = (1..count).map(|n| ((random() % n as u64) as u32, n)).collect::<Vec<_>>();
+            for a in 0..count {
+                for b in a + 1..count {
+                    if random() % 3 == 0 && !edges.contains(&(a, b)) {
+                        edges.push((a, b));
+                    }
+                }
+            }
+            let graph = test_graph(count, &edges);
+
+            let guard = SearchGuard::default();
+            guard.seed(0);
+            let route = find_longest_paths_guarded(graph, 0, &SearchOptions::default(), &guard);
+
+            assert_eq!(route.iter().chain([&0]).collect::<HashSet<_>>().len(), count as usize);
+            assert!(guard.subgraphs() <= 2 * count as usize, "{} subgraphs for {:?}", guard.subgraphs(), edges);
+        }
+    }
+
+    /// Shortest path costs between `count` stops on a random connected graph with weights 1 to 5.
+    fn random_stop_costs(random: &mut impl FnMut() -> u64, count: u32) -> Vec<Vec<f32>> {
+        let mut edges = (1..count).map(|n| ((random() % n as u64) as u32, n)).collect::<Vec<_>>();
+        for a in 0..count {
+            for b in a + 1..count {
+                if random().is_multiple_of(4) && !edges.contains(&(a, b)) {
+                    edges.push((a, b));
+                }
+            }
+        }
+        let mut graph = test_graph(count, &edges);
+        for connection in graph.edge_weights_mut() {
+            connection.weight = (1 + random() % 5) as f32;
+        }
+        graph.node_indices().map(|from| {
+            let distances = algo::dijkstra(&graph, from, None, |e| e.weight().weight);
+            graph.node_indices().map(|to| distances[&to]).collect()
+        }).collect()
+    }
+
+    fn brute_force_tour_cost(costs: &[Vec<f32>], closed: bool) -> f32 {
+        fn extend(costs: &[Vec<f32>], order: &mut Vec<usize>, best: &mut f32, closed: bool) {
+            if order.len() == costs.len() - 1 {
+                *best = best.min(tour_cost(costs, order, closed));
+                return;
+            }
+            for stop in 1..costs.len() {
+                if !order.contains(&stop) {
+                    order.push(stop);
+                    extend(costs, order, best, closed);
+                    order.pop();
+                }
+            }
+        }
+        let mut best = f32::INFINITY;
+        extend(costs, &mut Vec::new(), &mut best, closed);
+        best
+    }
+
+    #[test]
+    fn held_karp_matches_brute_force() {
+        let mut state = 0x9e3779b97f4a7c15u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for count in [2, 3, 5, 7] {
+            for _ in 0..10 {
+                let costs = random_stop_costs(&mut random, count);
+                for closed in [false, true] {
+                    let order = held_karp(&costs, &[], closed);
+                    assert_eq!(order.len(), count as usize - 1);
+                    assert_eq!(tour_cost(&costs, &order, closed), brute_force_tour_cost(&costs, closed), "{:?}", costs);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn approximate_tours_stay_within_twice_optimal() {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..50 {
+            let costs = random_stop_costs(&mut random, 8);
+            for closed in [false, true] {
+                let optimum = tour_cost(&costs, &held_karp(&costs, &[], closed), closed);
+                for (name, order) in [("mst", mst_tour(&costs)), ("approximate", approximate_tour(&costs, &[], closed))] {
+                    let mut stops = order.clone();
+                    stops.sort();
+                    assert_eq!(stops, (1..8).collect::<Vec<_>>(), "{} tour skips stops", name);
+                    let cost = tour_cost(&costs, &order, closed);
+                    assert!(cost <= 2.0 * optimum, "{} tour {} against optimum {} for {:?}", name, cost, optimum, costs);
+                }
+                assert!(tour_cost(&costs, &nearest_neighbour(&costs, &[]), closed) >= optimum);
+            }
+        }
+    }
+
+    #[test]
+    fn contracted_pipes_expand_to_the_uncontracted_route() {
+        // Pipe 0-1-2-3-4-5 into a triangle 5-6-7 with leaf 8, plus a short pipe 0-9-10 ending at a leaf
+        let graph = test_graph(11, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 6), (6, 7), (7, 5), (7, 8), (0, 9), (9, 10)]);
+        let contracted = Contracted::new(&graph, 0);
+        assert_eq!(contracted.graph.node_count(), 6);
+
+        let options = SearchOptions {
+            contract_pipes: true,
+            ..Default::default()
+        };
+        let route = find_longest_paths(graph.clone(), 0, &options);
+
+        assert_eq!(route, find_longest_paths(graph, 0, &SearchOptions::default()));
+        assert_eq!(route, [1, 2, 3, 4, 5, 6, 7, 8, 7, 5, 4, 3, 2, 1, 0, 9, 10, 9, 0]);
+    }
+
+    #[test]
+    fn contracted_path_runs_down_a_chain_back_to_itself() {
+        // Junctions 1, 4 and 7 in a cycle, joined by pipes 2-3 and 5-6, with leaves 8 and 9
+        let graph = test_graph(10, &[(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (5, 6), (6, 7), (7, 1), (4, 8), (7, 9)]);
+        let contracted = Contracted::new(&graph, 0);
+        let index = |id| node_index_of(&contracted.graph, id).unwrap();
+
+        let path = [index(0), index(1), index(7), index(4)];
+
+        assert_eq!(contracted.expand(&path), [0, 1, 7, 6, 5, 4, 3, 2]);
+        assert_eq!(contracted.expanded_len(&path), 8);
+    }
+
+    #[test]
+    fn trivial_route_skips_branching_graphs() {
+        let graph = test_graph(4, &[(0, 1), (0, 2), (0, 3)]);
+        assert!(trivial_route(&graph, 0).is_none());
+    }
+
+    #[test]
+    fn plan_request_follows_the_mode() {
+        // A square 0-1-2-3 with a tail 3-4
+        let graph = test_graph(5, &[(0, 1), (1, 2), (2, 3), (3, 0), (3, 4)]);
+        let options = SearchOptions::default();
+        let plan = |request: RouteRequest| plan_request(&graph, 0, &request, &options, &mut Vec::new()).unwrap();
+
+        let shortest = plan(RouteRequest { mode: Mode::Shortest, dest: Some(4), ..Default::default() });
+        assert_eq!(shortest.route, [3, 4]);
+        assert_eq!(shortest.strategy, Some("shortest"));
+
+        let postman = plan(RouteRequest { mode: Mode::Postman, ..Default::default() });
+        assert_eq!(postman.repeated, Some(1));
+        assert_eq!(postman.route.last(), Some(&0));
+
+        let targets = plan(RouteRequest { mode: Mode::Cover, targets: vec![2], ..Default::default() });
+        assert_eq!(targets.strategy, Some("targets"));
+        assert_eq!(targets.route.last(), Some(&2));
+
+        let ended = plan(RouteRequest { targets: vec![2, 4], end: Some(1), ..Default::default() });
+        assert_eq!(ended.route.last(), Some(&1));
+        assert!(ended.route.contains(&2) && ended.route.contains(&4));
+    }
+
+    #[test]
+    fn cover_route_passes_transit_only_systems_by() {
+        // 2 hangs off 1, and 3 off the start
+        let graph = test_graph(4, &[(0, 1), (1, 2), (0, 3)]);
+        assert_eq!(cover_route(&graph, 0, &HashSet::new(), None).len(), 6);
+
+        let route = cover_route(&graph, 0, &HashSet::from([2]), None);
+        assert!(!route.contains(&2));
+        assert_eq!(route.len(), 4);
+
+        let route = cover_route(&graph, 0, &HashSet::from([2]), Some(3));
+        assert_eq!(route, [1, 0, 3]);
+    }
+
+    #[test]
+    fn find_region_prefers_names_over_ids() {
+        let mut graph = test_graph(3, &[(0, 1), (1, 2)]);
+        for (i, (id, name)) in [(10, "Alpha"), (20, "10"), (30, "Gamma")].into_iter().enumerate() {
+            graph[NodeIndex::new(i)].region_id = id;
+            graph[NodeIndex::new(i)].region_name = Some(name.to_owned());
+        }
+
+        assert_eq!(find_region(&graph, "Gamma"), Some(30));
+        assert_eq!(find_region(&graph, "10"), Some(20));
+        assert_eq!(find_region(&graph, "30"), Some(30));
+        assert_eq!(find_region(&graph, "Delta"), None);
+        assert_eq!(find_region(&graph, "40"), None);
+    }
+
+    #[test]
+    fn find_constellation_checks_the_name_then_the_id() {
+        let mut graph = test_graph(2, &[(0, 1)]);
+        graph[NodeIndex::new(0)].constellation_id = Some(5);
+        graph[NodeIndex::new(0)].constellation_name = Some("Kalite".to_owned());
+
+        assert_eq!(find_constellation(&graph, "Kalite"), Some(5));
+        assert_eq!(find_constellation(&graph, "5"), Some(5));
+        assert_eq!(find_constellation(&graph, "6"), None);
+        assert_eq!(find_constellation(&graph, "Nod"), None);
+    }
+}
@@ -1,238 +1,1245 @@
-use serde::Deserialize;
-use std::fmt::Display;
-use std::io::{BufReader, Write};
+use std::io::{BufWriter, Write};
 use std::fs::File;
-use std::collections::{HashMap, HashSet};
-use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
-use petgraph::graph::{Graph, NodeIndex};
-use petgraph::{EdgeType, Undirected};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use petgraph::algo;
-use petgraph::visit::{Dfs, IntoNodeReferences, Walker};
-use petgraph::csr::IndexType;
-use petgraph::dot::{Dot, Config};
-use clap::Parser;
+use petgraph::visit::IntoNodeReferences;
+use clap::{Parser, Subcommand};
 use rayon::prelude::*;
+use frontier_route::*;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// Starting system, may be repeated to plan routes from several starts
+    #[arg(long, required = true)]
+    pub system: Vec<String>,
+
+    /// Map file to plan on, or for fetch-map to download into
+    #[arg(long, global = true, default_value = "map.json")]
+    pub map: String,
+
+    /// JSON object mapping alternative system names to system IDs, accepted wherever a system
+    /// is named
+    #[arg(long, global = true)]
+    pub aliases: Option<String>,
+
+    /// Show each system's aliases after its name in the output
+    #[arg(long, global = true, requires = "aliases")]
+    pub show_aliases: bool,
+
+    /// Name of the system ID field in the map
+    #[arg(long, global = true, default_value = "solarSystemID")]
+    pub id_field: String,
+
+    /// Name of the system name field in the map
+    #[arg(long, global = true, default_value = "solarSystemName")]
+    pub name_field: String,
+
+    /// Name of the neighbouring system IDs field in the map, e.g. "adjacent" or "gates"
+    #[arg(long, global = true, default_value = "neighbours")]
+    pub neighbours_field: String,
+
+    /// Split the region between this many pilots leaving from the start and print a route for
+    /// each, keeping the longest route as short as possible
+    #[arg(long, default_value_t = 1)]
+    pub pilots: usize,
+
+    /// Run every available strategy on the region, print how they compare and recommend the best
+    /// by this metric, without routing
+    #[arg(long, num_args = 0..=1, default_missing_value = "jumps")]
+    pub compare_strategies: Option<CompareMetric>,
+
+    /// Only visit these systems (comma separated names or IDs, or repeated as --via), in the order
+    /// with the fewest total jumps, instead of covering the region. Up to 12 are ordered exactly,
+    /// more with a heuristic tour improved by 2-opt
+    #[arg(long, visible_alias = "via", value_delimiter = ',', conflicts_with_all = ["resume_from", "prefix"])]
+    pub targets: Vec<String>,
+
+    /// Return to the start after the last --targets system, planning the cheapest closed loop
+    #[arg(long, requires = "targets", conflicts_with_all = ["end", "one_way"])]
+    pub round_trip: bool,
+
+    /// Visit system X before system Y when ordering --targets, given as X:Y and repeatable
+    #[arg(long, requires = "targets")]
+    pub before: Vec<String>,
+
+    /// Continue a previously saved partial route (JSON array of system IDs)
     #[arg(long)]
-    pub system: String,
+    pub resume_from: Option<String>,
+
+    /// Begin the route with these connected systems (names or IDs), then plan the rest from the
+    /// last of them
+    #[arg(long, num_args = 1.., conflicts_with = "resume_from")]
+    pub prefix: Vec<String>,
 
+    /// Only route through systems within this many jumps of the start
     #[arg(long)]
-    pub map: String,
-}
+    pub radius: Option<usize>,
 
-#[allow(non_snake_case, dead_code)]
-#[derive(Debug, Clone, Deserialize)]
-struct SolarSystem {
-    solarSystemID: u32,
-    solarSystemName: String,
-    neighbours: Vec<u32>,
-    stations: Vec<Station>,
-}
+    /// Write the route as a graphviz debug graph to the working directory, rendered to PNG when
+    /// graphviz is available
+    #[arg(long)]
+    pub dot: bool,
 
-#[allow(non_snake_case, dead_code)]
-#[derive(Debug, Clone, Deserialize)]
-struct Station {
-    stationID: u32,
-    stationName: String,
-}
+    /// Path to the graphviz `dot` binary used to render the debug graph
+    #[arg(long, requires = "dot")]
+    pub dot_path: Option<String>,
+
+    /// Report the two systems in the region furthest apart
+    #[arg(long)]
+    pub extremes: bool,
+
+    /// Start the route at the region extreme nearest to --system
+    #[arg(long)]
+    pub start_at_extreme: bool,
+
+    /// Load the built graph from this file if it exists, and save it (with edits) after. While
+    /// the file exists the map and its field names are not read, --weights still apply
+    #[arg(long)]
+    pub graph_cache: Option<String>,
+
+    /// Link two systems in otherwise separate regions, given as A:B (names or IDs), so one route
+    /// covers both. The link is shown as a link rather than a gate in the output, may be repeated
+    #[arg(long)]
+    pub link_regions: Vec<String>,
+
+    /// Add a wormhole connection between two systems (name or ID), may be repeated
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    pub add_edge: Vec<String>,
+
+    /// Remove the connection between two systems (name or ID), may be repeated
+    #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+    pub remove_edge: Vec<String>,
+
+    /// Exit with an error if unique systems / jumps falls below this ratio. A route over a tree
+    /// region visits each system twice, so 0.5 is a reasonable floor; cyclic regions can approach 1.0
+    #[arg(long)]
+    pub min_unique_ratio: Option<f32>,
+
+    /// What to plan from the start system
+    #[arg(long, value_enum, default_value_t)]
+    pub mode: Mode,
+
+    /// Destination system (name or ID) for --mode shortest
+    #[arg(long, required_if_eq("mode", "shortest"))]
+    pub dest: Option<String>,
+
+    /// Finish the route at this system (name or ID) instead of returning to the start, in any mode
+    /// but shortest, which finishes at --dest
+    #[arg(long, conflicts_with_all = ["dest", "route_in"])]
+    pub end: Option<String>,
+
+    /// Show the search's progress on stderr: each new peak candidate path length, with its
+    /// estimated memory, and each segment as it is found
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Exit with an error if any warnings were raised
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Warn about neighbour relations that are not listed in both directions
+    #[arg(long)]
+    pub check_symmetry: bool,
+
+    /// List the groups of systems with redundant connections between them, without routing
+    #[arg(long)]
+    pub blocks: bool,
+
+    /// List the region's dead-end systems by distance from the start, without routing
+    #[arg(long)]
+    pub leaves: bool,
+
+    /// List this many systems (default 10) lying on the most shortest paths between other
+    /// systems in the region, without routing
+    #[arg(long, num_args = 0..=1, default_missing_value = "10")]
+    pub centrality: Option<usize>,
+
+    /// Estimate whether the exhaustive search is tractable for the region, without routing
+    #[arg(long)]
+    pub sample: bool,
+
+    /// Append each system's gate count and security status to the text path
+    #[arg(long)]
+    pub annotate: bool,
+
+    /// JSON file of [from, to, weight] entries overriding the default weight of 1.0 per connection
+    #[arg(long)]
+    pub weights: Option<String>,
+
+    /// Render a route previously emitted by --output compact instead of computing one
+    #[arg(long)]
+    pub route_in: Option<String>,
+
+    /// Refuse to run the longest path search, as --mode tour, --pilots and --compare-strategies
+    /// do, on regions with more systems than this
+    #[arg(long)]
+    pub max_region_nodes: Option<usize>,
+
+    /// Maximum number of intermediate systems in each path searched
+    #[arg(long)]
+    pub max_path_len: Option<usize>,
+
+    /// Stop searching after this many seconds and output the partial route
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// Give each subgraph search, the main region and every straggler pickup, this many seconds
+    /// before keeping its best path so far and moving on, so one slow subgraph can't use up
+    /// --timeout for the rest
+    #[arg(long)]
+    pub subgraph_timeout: Option<f64>,
+
+    /// When --timeout is hit, retry with halved --max-path-len until the search completes in time
+    #[arg(long, requires = "timeout")]
+    pub retry_with_smaller_cap: bool,
+
+    /// Split the text route into legs of about this many jumps, ending at quiet systems
+    #[arg(long)]
+    pub leg_size: Option<usize>,
+
+    /// Label the debug graph's edges with their weights
+    #[arg(long, requires = "dot")]
+    pub dot_edge_labels: bool,
+
+    /// Decimal places for --dot-edge-labels
+    #[arg(long, default_value_t = 1)]
+    pub dot_precision: usize,
+
+    /// Count how many simple paths tie for the longest first segment from the start
+    #[arg(long)]
+    pub count_optima: bool,
+
+    /// Don't pass back through the start until its other reachable systems are covered
+    #[arg(long)]
+    pub no_start_reentry: bool,
+
+    /// Print search timing and throughput
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Prefer highsec systems, penalising each jump into a system below 0.5 security by this many
+    /// jumps (default 2) in both path selection and return legs
+    #[arg(long, num_args = 0..=1, default_missing_value = "2.0")]
+    pub prefer_highsec: Option<f32>,
+
+    /// Finish each constellation before moving on, penalising each move to another constellation
+    /// by this many jumps (default 1) when choosing paths
+    #[arg(long, num_args = 0..=1, default_missing_value = "1.0")]
+    pub cluster_by_constellation: Option<f32>,
+
+    /// Avoid jumping straight between highsec and nullsec, adding this many jumps (default 2) to
+    /// each such gate. Only the crossing is penalised, not lowsec systems as with --prefer-highsec
+    #[arg(long, num_args = 0..=1, default_missing_value = "2.0")]
+    pub border_penalty: Option<f32>,
+
+    /// Weight connections by security like the in-game autopilot, for return legs, waypoint
+    /// routes and --mode shortest
+    #[arg(long, value_enum, default_value_t)]
+    pub profile: Profile,
+
+    /// In tree regions, finish at the furthest system instead of walking back to the start
+    #[arg(long)]
+    pub no_return_on_leaf_only: bool,
+
+    /// Finish wherever the last new system is reached instead of returning to the start, taking
+    /// the trip with the longest way back last. Works for any region, but --mode postman still
+    /// closes its walk as its last connections may only be taken on the way back
+    #[arg(long, conflicts_with = "end")]
+    pub one_way: bool,
+
+    /// A system (name or ID) the route may pass through but doesn't need to visit, may be
+    /// repeated. Transit-only systems don't count towards coverage
+    #[arg(long)]
+    pub transit_only: Vec<String>,
+
+    /// Systems (names or IDs) already visited on this roam. The route from the current system
+    /// only covers the rest, passing through these where needed
+    #[arg(long, value_delimiter = ',')]
+    pub visited: Vec<String>,
+
+    /// JSON array of system IDs already visited, as with --visited
+    #[arg(long)]
+    pub visited_file: Option<String>,
+
+    /// Treat chains of systems with two connections as single hops while searching, which
+    /// shrinks the search for regions with long pipes. Has no effect with --max-path-len or
+    /// --max-mem
+    #[arg(long)]
+    pub contract_pipes: bool,
+
+    /// Never group the route by region in the output, for scripts expecting a plain list
+    #[arg(long)]
+    pub flat_output: bool,
+
+    /// Print a one line digest of the route for scheduling, added to the report as `summary`
+    #[arg(long)]
+    pub summary: bool,
+
+    /// After the first trip out from the start and back, stop routing through the start and pick
+    /// up the remaining systems along that trip. The route only comes back to the start again for
+    /// branches reachable through it alone
+    #[arg(long)]
+    pub release_start: bool,
+
+    /// Pass back through the start at most this many times before finishing there, 0 to only
+    /// come back at the end. Branches reachable through the start alone may be left unvisited
+    #[arg(long, conflicts_with_all = ["release_start", "no_start_reentry"])]
+    pub max_start_revisits: Option<usize>,
+
+    /// Draw the route in the text output as a tree from the start, each system under the one
+    /// the route first reached it from
+    #[arg(long)]
+    pub ascii_map: bool,
+
+    /// Trade route length against backtracking: each candidate segment loses this many systems
+    /// of length per jump (by connection weight) it needs to get back to the start, which the
+    /// route spends revisiting systems. At 1.0 a jump back costs as much as a new system gains
+    #[arg(long, default_value_t = 0.0)]
+    pub revisit_penalty: f32,
+
+    /// Shorten the finished route with up to this many local search passes, replacing stretches
+    /// that backtrack needlessly with shorter paths while visiting the same systems
+    #[arg(long)]
+    pub optimize: Option<usize>,
+
+    /// Enumerate every path even in regions with more than 30 independent cycles, which otherwise
+    /// use a faster beam search. Exact but can take exponentially long
+    #[arg(long)]
+    pub exact: bool,
+
+    /// Rough ceiling on the memory held by path enumeration, in bytes with an optional K, M or G
+    /// suffix. A target whose candidate paths grow past it is searched again with a length cap
+    #[arg(long, value_parser = parse_size)]
+    pub max_mem: Option<usize>,
+
+    /// Print a hash of the route, to detect changes between versions without storing it
+    #[arg(long)]
+    pub route_hash: bool,
+
+    /// strftime format for the local time in debug graph file names
+    #[arg(long, default_value = DEFAULT_TIMESTAMP_FORMAT)]
+    pub timestamp_format: String,
+
+    /// Leave the timestamp out of debug graph file names, overwriting the previous graph
+    #[arg(long, conflicts_with = "timestamp_format")]
+    pub no_timestamp: bool,
+
+    /// Write the region graph the router sees to this file, in the --graph-cache format
+    #[arg(long)]
+    pub dump_region: Option<String>,
+
+    /// Narrate the routing decisions to stderr
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Estimated seconds to jump through a stargate, including aligning and warping to it
+    #[arg(long, default_value_t = TravelTimes::default().gate)]
+    pub gate_seconds: f32,
+
+    /// Estimated seconds to jump through a jump bridge
+    #[arg(long, default_value_t = TravelTimes::default().bridge)]
+    pub bridge_seconds: f32,
+
+    /// Estimated seconds to jump through a wormhole
+    #[arg(long, default_value_t = TravelTimes::default().wormhole)]
+    pub wormhole_seconds: f32,
+
+    /// Only route through systems in this security band, may be repeated to keep several
+    #[arg(long, value_enum)]
+    pub security_class: Vec<SecurityClass>,
+
+    /// Leave out every system in this region (by name, or by region ID) before finding the start's
+    /// region. Region names come from maps downloaded with fetch-map
+    #[arg(long)]
+    pub avoid_region: Vec<String>,
+
+    /// Leave out every system in this constellation (by name, or by constellation ID), may be
+    /// repeated. Constellation names come from maps downloaded with fetch-map
+    #[arg(long)]
+    pub avoid_constellation: Vec<String>,
+
+    /// Leave out this system (name or ID) before routing, e.g. a camped chokepoint, may be repeated
+    #[arg(long)]
+    pub avoid: Vec<String>,
+
+    /// Leave out the systems in this JSON array of system IDs
+    #[arg(long)]
+    pub avoid_file: Option<String>,
 
-#[derive(Clone, Debug)]
-pub struct System {
-    pub id: u32,
-    pub name: String,
-    pub has_station: bool,
+    /// Write the route output to this file instead of stdout
+    #[arg(long)]
+    pub out_file: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
 }
 
-#[derive(Clone)]
-pub struct SystemWithJump {
-    pub id: u32,
-    pub name: String,
-    pub jumps: Vec<usize>,
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Check a map file for data problems, and optionally against the live ESI universe
+    Verify {
+        /// Cross-check system names and gates against ESI
+        #[arg(long)]
+        esi: bool,
+    },
+    /// Download systems and stargates from ESI into the --map file, kept until ESI's expiry
+    FetchMap {
+        /// Download again even if the cached map hasn't expired
+        #[arg(long)]
+        force: bool,
+    },
+    /// Find the system that is quickest for several members to gather at
+    Rally {
+        /// A member's current system (name or ID), repeat for each member
+        #[arg(long, required = true)]
+        from: Vec<String>,
+        /// Minimise the total jumps of all members, or the jumps of the furthest member
+        #[arg(long, value_enum, default_value_t = RallyMetric::Sum)]
+        metric: RallyMetric,
+    },
+    /// List the simple paths between two systems, shortest first
+    Paths {
+        /// System (name or ID) the paths start from
+        #[arg(long)]
+        from: String,
+        /// System (name or ID) the paths lead to
+        #[arg(long)]
+        to: String,
+        /// Longest path to consider, in jumps
+        #[arg(long)]
+        max_len: usize,
+        /// Print at most this many paths
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
 }
 
-impl Display for SystemWithJump {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} {:?}", self.name, self.jumps)
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
     }
 }
 
-pub fn debug_graph(graph: &Graph<System, f32, Undirected>, path: &Vec<u32>, name: String) {
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+fn run(mut args: Cli) -> Result<(), RouteError> {
+    // Aliases become system IDs before any lookup, which accepts IDs as well as names
+    let aliases = args.aliases.as_deref().map(load_aliases).transpose()?.unwrap_or_default();
+    if !aliases.is_empty() {
+        let resolve = |names: &mut Vec<String>| names.iter_mut().for_each(|name| *name = resolve_alias(&aliases, name));
+        resolve(&mut args.system);
+        resolve(&mut args.targets);
+        resolve(&mut args.prefix);
+        resolve(&mut args.add_edge);
+        resolve(&mut args.remove_edge);
+        resolve(&mut args.transit_only);
+        resolve(&mut args.visited);
+        resolve(&mut args.avoid);
+        args.end = args.end.map(|name| resolve_alias(&aliases, &name));
+        args.dest = args.dest.map(|name| resolve_alias(&aliases, &name));
+        match &mut args.command {
+            Some(Commands::Rally { from, .. }) => resolve(from),
+            Some(Commands::Paths { from, to, .. }) => {
+                *from = resolve_alias(&aliases, from);
+                *to = resolve_alias(&aliases, to);
+            }
+            _ => {}
+        }
+    }
+
+    let mut warnings = Vec::<String>::new();
 
-    let dot_file = format!("graph_{}_{}.dot", name, timestamp);
+    let field_names = FieldNames {
+        id: args.id_field.clone(),
+        name: args.name_field.clone(),
+        neighbours: args.neighbours_field.clone(),
+    };
 
-    let mut jump_positions = HashMap::<u32,Vec<usize>>::new();
-    for (position, id) in path.iter().enumerate() {
-        jump_positions.entry(*id).or_default().push(position);
+    if let Some(Commands::FetchMap { force }) = &args.command {
+        match fetch_map(&args.map, *force)? {
+            Fetched::Fresh => println!("{} is up to date, see {} for when it expires", args.map, cache_info_path(&args.map)),
+            Fetched::Unchanged => println!("{} is unchanged on ESI", args.map),
+            Fetched::Downloaded(systems) => println!("Fetched {} systems into {}", systems, args.map),
+        }
+        return Ok(());
     }
 
-    let graph_with_jumps = graph.filter_map(|_, s| {
-        Some(SystemWithJump {
-            id: s.id,
-            name: s.name.clone(),
-            jumps: jump_positions.get(&s.id).cloned().unwrap_or_default(),
-        })
-    }, |_, d| Some(d.clone()));
-
-    let mut f = File::create(&dot_file).unwrap();
-    let output = format!("{}", Dot::with_config(&graph_with_jumps, &[Config::EdgeNoLabel]));
-    f.write_all(&output.as_bytes()).unwrap();
+    if let Some(Commands::Verify { esi }) = &args.command {
+        let problems = verify_map(&load_map(&args.map, &field_names)?, *esi);
+        println!("Problems ({}):", problems.len());
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+        return Ok(());
+    }
 
-    let _ = Command::new("dot")
-        .args(["-Tpng".to_owned(), format!("-ograph_{}_{}.png", name, timestamp), dot_file])
-        .spawn();
-}
+    let pairs = |names: &[String]| names.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+    let mut graph = GraphSource {
+        map: args.map.clone(),
+        fields: field_names,
+        cache: args.graph_cache.clone(),
+        check_symmetry: args.check_symmetry,
+        weights: args.weights.as_deref().map(load_weights).transpose()?.unwrap_or_default(),
+        add_edges: pairs(&args.add_edge),
+        remove_edges: pairs(&args.remove_edge),
+    }.load(&mut warnings)?;
 
-pub fn filter_nodes<'a, N, E, Ty, Ix, F>(
-    graph: &'a Graph<N, E, Ty, Ix>,
-    mut node_map: F,
-) -> Graph<N, E, Ty, Ix>
-where
-    F: FnMut(NodeIndex<Ix>, &'a N) -> bool,
-    Ty: EdgeType,
-    Ix: IndexType,
-    N: Clone,
-    E: Clone,
-{
-    graph.filter_map(|i, n| {
-        if node_map(i, n) {
-            Some(n.clone())
+    // Links only join regions for this run, so they stay out of the graph cache
+    let mut links = Vec::new();
+    for spec in &args.link_regions {
+        let (a, b) = parse_system_pair(spec, |name| find_system(&graph, &resolve_alias(&aliases, name)).map(|n| graph[n].id))
+            .map_err(|e| RouteError::InvalidArgument(format!("Invalid --link-regions: {}", e)))?;
+        if add_connection(&mut graph, a, b, ConnectionKind::Link) {
+            links.push(sort_tuple((a, b)));
         } else {
-            None
+            warnings.push(format!("{} is already connected", spec));
         }
-    }, |_, e| Some(e.clone()))
-}
+    }
 
-pub fn find_longest_paths(original_graph: Graph<System, f32, Undirected>, start_id: u32) -> Vec<u32> {
-    let mut graph = original_graph.clone();
-    let mut result = Vec::<u32>::new();
-
-    loop {
-        let start_index = graph.node_references().find(|(_, system)| {
-            system.id == start_id
-        }).expect("Start node disappeared").0;
-
-        if let Some(longest_path) = graph.node_indices().par_bridge().filter_map(|n| {
-            algo::all_simple_paths(&graph, start_index, n, 0, None).max_by_key(|v: &Vec<NodeIndex>| v.len())
-        }).max_by_key(|v| v.len()) {
-            let longest_path = longest_path.into_iter().skip(1).collect::<Vec<_>>();
-
-            let return_path = algo::astar(
-                &graph,
-                *longest_path.last().expect("Got an empty path"),
-                |n| n == start_index,
-                |_| 1,
-                |_| 0,
-            ).expect("Cannot return to start").1.into_iter().skip(1).collect::<Vec<_>>();
-            
-            let full_path = longest_path.into_iter().chain(return_path.into_iter()).collect::<Vec<_>>();
-            let full_path_id = full_path.iter().map(|n| graph[*n].id).collect::<Vec<_>>();
-
-            result.extend(full_path_id);
-
-            graph = filter_nodes(&graph, |i, _| !full_path.contains(&i) || i == start_index);
-        } else {
-            break;
+    if let Some(Commands::Rally { from, metric }) = &args.command {
+        let members = from.iter()
+            .map(|name| require_system(&graph, name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (rally, jumps) = rally_point(&graph, &members, *metric)
+            .ok_or_else(|| RouteError::Disconnected("No system is reachable by every member".to_owned()))?;
+
+        println!("Rally point: {} (total {} jumps, furthest {} jumps)", graph[rally].name, jumps.iter().sum::<usize>(), jumps.iter().max().unwrap_or(&0));
+        for (member, jumps) in members.iter().zip(jumps) {
+            let (_, path) = algo::astar(&graph, *member, |n| n == rally, |_| 1usize, |_| 0).expect("Rally point disappeared");
+            let names = path.iter().map(|n| graph[*n].name.clone()).collect::<Vec<_>>();
+            println!("  {} ({} jumps): {:?}", graph[*member].name, jumps, names);
+        }
+        return Ok(());
+    }
+
+    apply_travel_times(&mut graph, &TravelTimes {
+        gate: args.gate_seconds,
+        bridge: args.bridge_seconds,
+        wormhole: args.wormhole_seconds,
+    });
+
+    if let Some(Commands::Paths { from, to, max_len, limit }) = &args.command {
+        let from = require_system(&graph, from)?;
+        let to = require_system(&graph, to)?;
+        let (paths, total) = shortest_simple_paths(&graph, from, to, *max_len, *limit);
+
+        println!("Paths from {} to {} within {} jumps: {}", graph[from].name, graph[to].name, max_len, total);
+        for path in &paths {
+            let names = path.iter().map(|n| graph[*n].name.as_str()).collect::<Vec<_>>();
+            println!("  {} jumps: {}", path.len() - 1, names.join(" -> "));
+        }
+        if total > paths.len() {
+            println!("Showing the shortest {} of {}, raise --limit to see more", paths.len(), total);
         }
+        return Ok(());
     }
 
-    let mut final_result = Vec::<u32>::new();
-    let mut visited = HashSet::<u32>::new();
-    visited.insert(start_id);
+    let graph = narrow_graph(&args, graph, &mut warnings)?;
 
-    for id in &result {
-        final_result.push(*id);
+    //println!("Entire game cyclic: {}", algo::is_cyclic_undirected(&graph));
 
-        if !visited.contains(id) {
-            let sub_graph = filter_nodes(&original_graph, |_, n| (!result.contains(&n.id) && !final_result.contains(&n.id)) || n.id == *id);
-            let path = find_longest_paths(sub_graph.clone(), *id);
-            
-            final_result.extend(path);
+    let out_path = args.out_file.clone().unwrap_or_else(|| "stdout".to_owned());
+    let mut out: Box<dyn Write> = match &args.out_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path).map_err(|source| RouteError::Io { path: path.clone(), source })?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let out_error = |source: std::io::Error| RouteError::Io { path: out_path.clone(), source };
 
-            visited.insert(*id);
+    if args.output == OutputFormat::Text && args.mode != Mode::Shortest {
+        writeln!(out, "Cyclic regions:").map_err(out_error)?;
+        for n in graph.node_weights() {
+            if n.has_station {
+                let graph = region_of(&graph, n.id);
+                if algo::is_cyclic_undirected(&graph) {
+                    writeln!(out, "  {} - Nodes: {}, Edges: {}", n.name, graph.node_count(), graph.edge_count()).map_err(out_error)?;
+                }
+            }
         }
     }
 
-    final_result
-}
+    let mut transit_only = HashSet::new();
+    for name in args.transit_only.iter().chain(&args.visited) {
+        transit_only.insert(graph[require_system(&graph, name)?].id);
+    }
+    if let Some(path) = &args.visited_file {
+        transit_only.extend(load_route(path)?);
+    }
+
+    let search_options = SearchOptions {
+        max_path_len: args.max_path_len,
+        deadline: args.timeout.map(|timeout| Instant::now() + Duration::from_secs(timeout)),
+        no_start_reentry: args.no_start_reentry,
+        explain: args.explain,
+        contract_pipes: args.contract_pipes,
+        max_mem: args.max_mem,
+        memory: Arc::default(),
+        revisit_penalty: args.revisit_penalty,
+        exact: args.exact,
+        progress: args.progress,
+        release_start: args.release_start,
+        max_start_revisits: args.max_start_revisits,
+        subgraph_timeout: args.subgraph_timeout.map(Duration::from_secs_f64),
+        subgraph_deadline: None,
+        time_limited: Arc::default(),
+        transit_only,
+        scorer: match (args.prefer_highsec, args.cluster_by_constellation) {
+            (None, None) => None,
+            (highsec, constellation) => Some(penalty_scorer(highsec.unwrap_or_default(), constellation.unwrap_or_default())),
+        },
+    };
+
+    let search_region = |id: u32| {
+        let region = region_of(&graph, id);
+        match args.radius {
+            Some(radius) => {
+                let start_index = node_index_of(&region, id).expect("Start node disappeared");
+                within_radius(&region, start_index, radius)
+            }
+            None => region,
+        }
+    };
+
+    // Checked for every start before any of them is searched. Only the longest path search grows
+    // exponentially, the region reports and the other modes don't run it
+    let reports_only = args.sample || args.blocks || args.leaves || args.centrality.is_some() || args.output == OutputFormat::Adjacency;
+    let longest_search = !reports_only && (args.compare_strategies.is_some() || args.pilots > 1
+        || (args.route_in.is_none() && args.mode == Mode::Tour && args.targets.is_empty()));
+    if let (Some(max_nodes), true) = (args.max_region_nodes, longest_search) {
+        for name in &args.system {
+            let system = &graph[require_system(&graph, name)?];
+            let systems = search_region(system.id).node_count();
+            if systems > max_nodes {
+                return Err(RouteError::InvalidArgument(format!(
+                    "Region of {} has {} systems, more than --max-region-nodes {}. The longest path search grows exponentially with region size, reduce the region with --radius",
+                    system.name, systems, max_nodes)));
+            }
+        }
+    }
+
+    let batch_starts = args.system[1..].iter()
+        .map(|name| Ok(graph[require_system(&graph, name)?].id))
+        .collect::<Result<Vec<_>, RouteError>>()?;
+
+    let mut all_names = graph.node_weights().map(|n| (n.id, n.name.clone())).collect::<HashMap<_, _>>();
+    if args.show_aliases {
+        show_aliases(&mut all_names, &aliases);
+    }
+
+    let start_system = &graph[require_system(&graph, &args.system[0])?];
+
+    let graph = search_region(start_system.id);
+
+    if let Some(path) = &args.dump_region {
+        save_graph(&graph, path)?;
+    }
+
+    let mut start_id = start_system.id;
+
+    if write_region_report(&args, &graph, start_id, &mut out).map_err(out_error)? {
+        return out.flush().map_err(out_error);
+    }
+
+    if args.extremes || args.start_at_extreme {
+        let (a, b, distance) = region_extremes(&graph).expect("Region is empty");
+
+        if args.output == OutputFormat::Text {
+            writeln!(out, "Extremes: {} <-> {} ({} jumps)", graph[a].name, graph[b].name, distance).map_err(out_error)?;
+        }
+
+        if args.start_at_extreme {
+            let start_index = node_index_of(&graph, start_id).expect("Start node disappeared");
+            let distances = algo::dijkstra(&graph, start_index, None, |_| 1usize);
+            start_id = graph[if distances[&a] <= distances[&b] { a } else { b }].id;
+        }
+    }
+
+    if args.output == OutputFormat::Text && args.mode != Mode::Shortest {
+        writeln!(out, "Region cyclic: {}", algo::is_cyclic_undirected(&graph)).map_err(out_error)?;
+        if graph.node_count() < SMALL_REGION {
+            writeln!(out, "Note: Region has only {} systems, so the route is short", graph.node_count()).map_err(out_error)?;
+        }
+    }
+
+    if args.output == OutputFormat::Adjacency {
+        writeln!(out, "{}", serde_json::to_string_pretty(&adjacency(&graph)).expect("Serialization failed")).map_err(out_error)?;
+        return out.flush().map_err(out_error);
+    }
+
+    if args.count_optima && args.output == OutputFormat::Text {
+        let start_index = node_index_of(&graph, start_id).expect("Start node disappeared");
+        let optima = count_optima(&graph, start_index, &search_options);
+        let examples = optima.endpoints.iter().take(3).map(|n| graph[*n].name.clone()).collect::<Vec<_>>();
+        let saturated = if optima.count >= OPTIMA_CAP { "at least " } else { "" };
+        writeln!(out, "Longest first segment: {} jumps, {}{} paths of that length, ending at {}{}",
+            optima.jumps, saturated, optima.count, examples.join(", "),
+            if optima.endpoints.len() > examples.len() { ", ..." } else { "" }).map_err(out_error)?;
+    }
+
+    let mut request = route_request(&args, &aliases, &graph, start_id)?;
+
+    if let Some(metric) = args.compare_strategies {
+        write_comparison(&mut out, &graph, start_id, &search_options, &request, metric).map_err(out_error)?;
+        return out.flush().map_err(out_error);
+    }
+
+    if args.pilots > 1 {
+        write_pilots(&mut out, &graph, start_id, &search_options, args.pilots, &all_names).map_err(out_error)?;
+        return out.flush().map_err(out_error);
+    }
+
+    // The other starts get the same request, resolved in their own regions
+    let batch = batch_starts.par_iter().map(|id| {
+        let region = search_region(*id);
+        let request = route_request(&args, &aliases, &region, *id)?;
+        let mut batch_warnings = Vec::new();
+        let planned = plan_request(&region, *id, &request, &search_options, &mut batch_warnings)?;
+        Ok((*id, planned.route, batch_warnings))
+    }).collect::<Result<Vec<_>, RouteError>>()?;
+    let mut batch_routes = BTreeMap::new();
+    for (id, route, batch_warnings) in batch {
+        warnings.extend(batch_warnings.into_iter().map(|warning| format!("From {}: {}", all_names[&id], warning)));
+        batch_routes.insert(id, route);
+    }
+
+    let search_started = Instant::now();
+
+    request.route = match &args.route_in {
+        Some(route_in) => {
+            let route = parse_compact_route(route_in)
+                .map_err(|e| RouteError::InvalidArgument(format!("Invalid compact route: {}", e)))?;
+            for id in &route {
+                if !all_names.contains_key(id) {
+                    return Err(RouteError::UnknownSystem(id.to_string()));
+                }
+                if node_index_of(&graph, *id).is_none() {
+                    return Err(RouteError::Disconnected(format!("Route system {} is not in the region of {}", all_names[id], start_system.name)));
+                }
+            }
+            Some(route)
+        }
+        None => None,
+    };
+    let planned = plan_request(&graph, start_id, &request, &search_options, &mut warnings)?;
+    let result = planned.route;
+    let return_suppressed = planned.return_suppressed;
+
+    if args.output == OutputFormat::Text {
+        if let Some(repeated) = planned.repeated {
+            writeln!(out, "Connections: {}, repeated jumps: {}", graph.edge_count(), repeated).map_err(out_error)?;
+        }
+        if let Some((saved, replaced)) = planned.optimized {
+            writeln!(out, "Optimized: {} jumps saved by replacing {} stretches", saved, replaced).map_err(out_error)?;
+        }
+    }
 
-pub fn sort_tuple<T>(v: (T, T)) -> (T, T)
-where  T: PartialOrd {
-    if v.0 < v.1 {
-        (v.0, v.1)
-    } else {
-        (v.1, v.0)
+    let search_elapsed = search_started.elapsed();
+
+    for (id, size) in search_options.time_limited.lock().expect("Time limited list poisoned").iter() {
+        warnings.push(format!("Search of {} systems from {} ran out of --subgraph-timeout, its best path so far was kept", size, all_names[id]));
+    }
+
+    if let (capped @ 1.., Some(cap)) = (search_options.memory.capped(), search_options.memory_cap()) {
+        warnings.push(format!("{} path searches went over --max-mem and were capped at {} systems", capped, cap));
+    }
+
+    // Only targets and shortest mode set out to skip systems, anything else missing is a coverage failure
+    let missed = if args.targets.is_empty() && args.mode != Mode::Shortest { missed_systems(&graph, start_id, &result, &search_options.transit_only) } else { Vec::new() };
+    if !missed.is_empty() {
+        let mut names = missed.iter().map(|id| all_names[id].as_str()).collect::<Vec<_>>();
+        names.sort();
+        eprintln!("Coverage check failed: {} systems in the region were not visited", missed.len());
+        warnings.push(format!("{} systems in the region were not visited: {}", missed.len(), names.join(", ")));
+    }
+
+    if args.dot {
+        debug_graph(&graph, &result, args.system[0].clone(), &DotOptions {
+            dot_path: args.dot_path.as_deref(),
+            edge_label_precision: args.dot_edge_labels.then_some(args.dot_precision),
+            timestamp_format: (!args.no_timestamp).then_some(args.timestamp_format.as_str()),
+            start_id: Some(start_id),
+        }, &mut warnings);
+    }
+
+    let mut exit_code = 0;
+
+    if let Some(min_ratio) = args.min_unique_ratio {
+        let ratio = unique_ratio(&result);
+        if ratio < min_ratio {
+            warnings.push(format!("Unique systems per jump {:.2} is below --min-unique-ratio {:.2}", ratio, min_ratio));
+            exit_code = 2;
+        }
+    }
+
+    if args.strict && !warnings.is_empty() {
+        exit_code = 1;
+    }
+
+    let mut name_lookup = HashMap::<u32,String>::new();
+    for (i, n) in graph.node_references() {
+        let name = if args.annotate { annotate_system(&graph, i) } else { n.name.clone() };
+        name_lookup.insert(n.id, name);
+    }
+    if args.show_aliases {
+        show_aliases(&mut name_lookup, &aliases);
+    }
+
+    write_route(&mut out, &args, RouteOutput {
+        graph: &graph,
+        start_id,
+        result: &result,
+        strategy: planned.strategy,
+        return_suppressed,
+        batch_routes,
+        links: &links,
+        all_names: &all_names,
+        name_lookup: &name_lookup,
+        warnings: &warnings,
+        missed: missed.len(),
+        search_elapsed,
+        search_options: &search_options,
+    }).map_err(out_error)?;
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
     }
+
+    Ok(())
 }
 
-fn main() {
-    let args = Cli::parse();
 
-    let file = File::open(args.map).expect("data.json not found");
-    let reader = BufReader::new(file);
-    let data : HashMap<u32, SolarSystem> = serde_json::from_reader(reader).expect("Deserialization failed");
+/// Resolves the route options for a start in its region.
+fn route_request(args: &Cli, aliases: &HashMap<String, u32>, graph: &MapGraph, start_id: u32) -> Result<RouteRequest, RouteError> {
+    let start_name = &graph[node_index_of(graph, start_id).expect("Start node disappeared")].name;
+
+    let before = args.before.iter()
+        .map(|spec| parse_system_pair(spec, |name| find_system(graph, &resolve_alias(aliases, name)).map(|n| graph[n].id)))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| RouteError::InvalidArgument(format!("Invalid --before: {}", e)))?;
+    if precedence_cycle(&before) {
+        return Err(RouteError::InvalidArgument("The --before constraints contradict each other".to_owned()));
+    }
+    if let Some((x, _)) = before.iter().find(|(_, y)| *y == start_id) {
+        return Err(RouteError::InvalidArgument(format!("{} can't be visited before the start", graph[node_index_of(graph, *x).expect("Stop disappeared")].name)));
+    }
+
+    let targets = args.targets.iter()
+        .map(|name| find_system(graph, name).map(|n| graph[n].id)
+            .ok_or_else(|| RouteError::Disconnected(format!("Target system {} is not in the region", name))))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let mut graph = Graph::<System, f32, Undirected>::new_undirected();
-    let mut node_index = HashMap::<u32, NodeIndex>::new();
+    let dest = match &args.dest {
+        Some(dest) if args.mode == Mode::Shortest => Some(graph[require_system(graph, dest)
+            .map_err(|_| RouteError::Disconnected(format!("{} can't be reached from {}", dest, start_name)))?].id),
+        _ => None,
+    };
+    let end = match &args.end {
+        Some(end) => Some(graph[find_system(graph, end)
+            .ok_or_else(|| RouteError::Disconnected(format!("End system {} is not in the region", end)))?].id),
+        None => None,
+    };
 
-    for (_, ss) in data.iter() {
-        node_index.insert(ss.solarSystemID, graph.add_node(System {
-            id: ss.solarSystemID,
-            name: ss.solarSystemName.clone(),
-            has_station: ss.stations.len() > 0,
-        }));
+    Ok(RouteRequest {
+        mode: args.mode,
+        dest,
+        end,
+        targets,
+        before,
+        round_trip: args.round_trip,
+        route: None,
+        resume: args.resume_from.as_deref().map(load_route).transpose()?,
+        prefix: args.prefix.clone(),
+        retry_within: args.timeout.filter(|_| args.retry_with_smaller_cap).map(Duration::from_secs),
+        no_return_on_leaf_only: args.no_return_on_leaf_only,
+        one_way: args.one_way,
+        optimize: args.optimize,
+    })
+}
+/// Applies the security preferences to the connection weights and leaves out the avoided
+/// systems, checking no start system is among them.
+fn narrow_graph(args: &Cli, mut graph: MapGraph, warnings: &mut Vec<String>) -> Result<MapGraph, RouteError> {
+    if args.leg_size.is_some() && graph.node_weights().all(|n| n.security.is_none()) {
+        warnings.push("--leg-size picks rest points by gate count alone, the map has no security data".to_owned());
+    }
+
+    if let Some(penalty) = args.border_penalty {
+        if graph.node_weights().all(|n| n.security.is_none()) {
+            warnings.push("--border-penalty has no effect, the map has no security data".to_owned());
+        }
+        apply_border_penalty(&mut graph, penalty);
     }
 
-    let mut added = HashSet::<(u32,u32)>::new();
-    for (_, ss) in data.iter() {
-        let index1 = *node_index.get(&ss.solarSystemID).unwrap();
-        for n in &ss.neighbours {
-            let index2 = *node_index.get(n).unwrap();
-            let system_pair = sort_tuple((ss.solarSystemID, *n));
-            if !added.contains(&system_pair) {
-                graph.add_edge(index1, index2, 1.0);
-                added.insert(system_pair);
+    if let Some(penalty) = args.prefer_highsec {
+        if graph.node_weights().all(|n| n.security.is_none()) {
+            warnings.push("--prefer-highsec has no effect, the map has no security data".to_owned());
+        }
+        apply_highsec_penalty(&mut graph, penalty);
+    }
+
+    if args.profile != Profile::Shorter {
+        if graph.node_weights().all(|n| n.security.is_none()) {
+            warnings.push("--profile has no effect, the map has no security data".to_owned());
+        }
+        apply_profile(&mut graph, args.profile);
+    }
+
+    if !args.avoid_region.is_empty() {
+        let regions = args.avoid_region.iter()
+            .map(|name| find_region(&graph, name).ok_or_else(|| RouteError::InvalidArgument(format!("Region {} is not on the map", name))))
+            .collect::<Result<HashSet<_>, _>>()?;
+        for name in &args.system {
+            let start = &graph[require_system(&graph, name)?];
+            if regions.contains(&start.region_id) {
+                let region = start.region_name.clone().unwrap_or_else(|| start.region_id.to_string());
+                return Err(RouteError::Disconnected(format!("Starting system {} is in avoided region {}", name, region)));
             }
         }
+        graph = filter_nodes(&graph, |_, n| !regions.contains(&n.region_id));
     }
 
-    //println!("Entire game cyclic: {}", algo::is_cyclic_undirected(&graph));
+    let mut avoid = HashSet::new();
+    for name in &args.avoid {
+        avoid.insert(graph[require_system(&graph, name)?].id);
+    }
+    if let Some(path) = &args.avoid_file {
+        avoid.extend(load_route(path)?);
+    }
+    let constellations = args.avoid_constellation.iter()
+        .map(|name| find_constellation(&graph, name).ok_or_else(|| RouteError::InvalidArgument(format!("Constellation {} is not on the map", name))))
+        .collect::<Result<HashSet<_>, _>>()?;
+    if !avoid.is_empty() || !constellations.is_empty() {
+        let avoided = |n: &System| avoid.contains(&n.id) || n.constellation_id.is_some_and(|c| constellations.contains(&c));
+        for name in &args.system {
+            let start = require_system(&graph, name)?;
+            if avoided(&graph[start]) {
+                return Err(RouteError::Disconnected(format!("Starting system {} is avoided", name)));
+            }
+        }
+        graph = filter_nodes(&graph, |_, n| !avoided(n));
+    }
 
-    println!("Cyclic regions:");
-    for (start_node, n) in graph.node_references() {
-        if n.has_station {
-            let nodes = Dfs::new(&graph, start_node).iter(&graph).collect::<HashSet<_>>();
-            let graph = filter_nodes(&graph, |i, _| nodes.contains(&i));
-            if algo::is_cyclic_undirected(&graph) {
-                println!("  {} - Nodes: {}, Edges: {}", n.name, graph.node_count(), graph.edge_count());
+    if !args.security_class.is_empty() {
+        if graph.node_weights().all(|n| n.security.is_none()) {
+            warnings.push("--security-class has no effect, the map has no security data".to_owned());
+        }
+        for name in &args.system {
+            let start = &graph[require_system(&graph, name)?];
+            if !args.security_class.contains(&SecurityClass::of(start)) {
+                return Err(RouteError::Disconnected(format!("Starting system {} is {:?}, outside --security-class", name, SecurityClass::of(start))));
             }
         }
+        graph = filter_nodes(&graph, |_, n| args.security_class.contains(&SecurityClass::of(n)));
+    }
+
+    Ok(graph)
+}
+
+/// Writes the region report asked for instead of a route, if any, returning whether it did.
+fn write_region_report(args: &Cli, graph: &MapGraph, start_id: u32, out: &mut dyn Write) -> std::io::Result<bool> {
+    if args.sample {
+        let start_index = node_index_of(graph, start_id).expect("Start node disappeared");
+        let estimate = sample_search(graph, start_index, Duration::from_secs(2));
+        writeln!(out, "Region: {} systems, {} connections", graph.node_count(), graph.edge_count())?;
+        writeln!(out, "Sampled {} paths up to {} intermediate systems in {:.2}s", estimate.paths, estimate.max_length, estimate.elapsed.as_secs_f64())?;
+        writeln!(out, "Branching factor: {:.2}", estimate.branching_factor)?;
+        writeln!(out, "Estimated paths for full search: {:.1e}", estimate.estimated_paths)?;
+        if estimate.tractable() {
+            writeln!(out, "Recommendation: the full search is likely tractable")?;
+        } else {
+            writeln!(out, "Recommendation: the full search is unlikely to finish, reduce the region with --radius")?;
+        }
+        return Ok(true);
     }
 
-    let (start_node, start_system) = graph.node_references().find(|(_, system)| {
-        system.name == args.system
-    }).expect("Starting system not found");
+    if args.blocks {
+        let blocks = blocks(graph);
+        let (redundant, single) = blocks.iter().partition::<Vec<_>, _>(|b| b.len() > 1);
+        writeln!(out, "Blocks ({}):", redundant.len())?;
+        for block in redundant {
+            let mut names = block.iter().map(|n| graph[*n].name.as_str()).collect::<Vec<_>>();
+            names.sort();
+            writeln!(out, "  {}: {}", names.len(), names.join(", "))?;
+        }
+        writeln!(out, "Systems without redundant connections: {}", single.len())?;
+        return Ok(true);
+    }
+
+    if let Some(count) = args.centrality {
+        let mut centrality = betweenness(graph).into_iter().collect::<Vec<_>>();
+        centrality.sort_by(|(a, ca), (b, cb)| cb.total_cmp(ca).then_with(|| graph[*a].name.cmp(&graph[*b].name)));
+        writeln!(out, "Most central systems ({} of {}):", count.min(centrality.len()), centrality.len())?;
+        for (n, value) in centrality.into_iter().take(count) {
+            writeln!(out, "  {} ({:.1})", graph[n].name, value)?;
+        }
+        return Ok(true);
+    }
 
-    let nodes = Dfs::new(&graph, start_node).iter(&graph).collect::<HashSet<_>>();
-    let graph = filter_nodes(&graph, |i, _| nodes.contains(&i));
+    if args.leaves {
+        let start_index = node_index_of(graph, start_id).expect("Start node disappeared");
+        let leaves = region_leaves(graph, start_index);
+        writeln!(out, "Leaves ({}):", leaves.len())?;
+        for (leaf, distance) in leaves {
+            writeln!(out, "  {} ({} jumps)", graph[leaf].name, distance)?;
+        }
+        return Ok(true);
+    }
 
-    println!("Region cyclic: {}", algo::is_cyclic_undirected(&graph));
+    Ok(false)
+}
 
-    let result = find_longest_paths(graph.clone(), start_system.id);
+/// What the route output formats draw on once the route is planned.
+struct RouteOutput<'a> {
+    graph: &'a MapGraph,
+    start_id: u32,
+    result: &'a [u32],
+    strategy: Option<&'static str>,
+    return_suppressed: bool,
+    batch_routes: BTreeMap<u32, Vec<u32>>,
+    links: &'a [(u32, u32)],
+    all_names: &'a HashMap<u32, String>,
+    name_lookup: &'a HashMap<u32, String>,
+    warnings: &'a [String],
+    missed: usize,
+    search_elapsed: Duration,
+    search_options: &'a SearchOptions,
+}
 
-    let mut name_lookup = HashMap::<u32,String>::new();
-    for (_, n) in graph.node_references() {
-        name_lookup.insert(n.id, n.name.clone());
+/// Writes the planned route in the format asked for.
+fn write_route(out: &mut dyn Write, args: &Cli, output: RouteOutput) -> std::io::Result<()> {
+    let RouteOutput { graph, start_id, result, strategy, return_suppressed, batch_routes, links, all_names, name_lookup, warnings, missed, search_elapsed, search_options } = output;
+    match args.output {
+        OutputFormat::Text if args.mode == Mode::Shortest => {
+            let dest = result.last().unwrap_or(&start_id);
+            writeln!(out, "Route from {} to {}: {} jumps, ETA {}", all_names[&start_id], all_names[dest], result.len(), format_eta(route_eta(graph, start_id, result)))?;
+            for line in jump_lines(graph, start_id, result, name_lookup) {
+                writeln!(out, "  {}", line)?;
+            }
+            if !warnings.is_empty() {
+                writeln!(out, "Warnings ({}):", warnings.len())?;
+                for warning in warnings {
+                    writeln!(out, "  {}", warning)?;
+                }
+            }
+        }
+        OutputFormat::Text => {
+            let connections = route_connections(graph, start_id, result);
+            let result_names = result.iter().zip(connections).map(|(id, kind)| {
+                let name = name_lookup.get(id).cloned().unwrap_or_default();
+                match kind {
+                    ConnectionKind::Gate => name,
+                    kind => format!("{} [{}]", name, kind),
+                }
+            }).collect::<Vec<_>>();
+            writeln!(out, "Jumps: {}", result_names.len())?;
+            writeln!(out, "Path: {:?}", result_names)?;
+            writeln!(out, "Path IDs: {:?}", result)?;
+            writeln!(out, "ETA: {}", format_eta(route_eta(graph, start_id, result)))?;
+            if args.mode == Mode::Cover {
+                writeln!(out, "Revisits: {}", revisits(result, start_id))?;
+            }
+
+            if args.summary {
+                writeln!(out, "Summary: {}", summarize(graph, start_id, result, args.leg_size))?;
+            }
+
+            let used_links = std::iter::once(&start_id).chain(result).collect::<Vec<_>>().windows(2)
+                .map(|w| sort_tuple((*w[0], *w[1])))
+                .filter(|pair| links.contains(pair))
+                .collect::<BTreeSet<_>>();
+            if !links.is_empty() {
+                writeln!(out, "Links used: {}", used_links.iter().map(|(a, b)| format!("{} - {}", all_names[a], all_names[b])).collect::<Vec<_>>().join(", "))?;
+            }
+
+            if args.ascii_map {
+                writeln!(out, "Route map:")?;
+                for line in route_tree(start_id, result, all_names) {
+                    writeln!(out, "  {}", line)?;
+                }
+            }
+
+            if return_suppressed {
+                writeln!(out, "Region is a tree, the final return to {} was dropped", all_names[&start_id])?;
+            } else if args.one_way && result.last() != Some(&start_id) {
+                writeln!(out, "One way, finishing at {} without returning to {}", all_names[result.last().unwrap_or(&start_id)], all_names[&start_id])?;
+            }
+
+            if args.cluster_by_constellation.is_some() {
+                writeln!(out, "Constellation transitions: {}", route_constellation_transitions(graph, start_id, result))?;
+            }
+
+            if args.route_hash {
+                writeln!(out, "Route hash: {}", route_hash(start_id, result))?;
+            }
+
+            if args.stats {
+                writeln!(out, "Computed {} jumps in {:.2}s ({:.1} systems/s)", result.len(), search_elapsed.as_secs_f64(), throughput(result, search_elapsed))?;
+                writeln!(out, "Peak candidate path: {} systems, about {} KiB of enumeration state", search_options.memory.peak_path(), search_options.memory.peak_bytes().div_ceil(1024))?;
+                writeln!(out, "Segments:")?;
+                writeln!(out, "  {:>3} {:>6} {:>5} {:>10}", "#", "jumps", "new", "backtrack")?;
+                for (number, segment) in segment_stats(result, start_id).iter().enumerate() {
+                    writeln!(out, "  {:>3} {:>6} {:>5} {:>9.0}%", number + 1, segment.jumps, segment.new_systems, segment.backtrack_ratio() * 100.0)?;
+                }
+            }
+
+            if let Some(leg_size) = args.leg_size {
+                writeln!(out, "Legs:")?;
+                for (number, leg) in split_legs(graph, result, leg_size).into_iter().enumerate() {
+                    let from = if leg.start == 0 { start_id } else { result[leg.start - 1] };
+                    writeln!(out, "  {}: jumps {}-{}, {} -> {}", number + 1, leg.start + 1, leg.end,
+                        all_names[&from], all_names[&result[leg.end - 1]])?;
+                }
+            }
+
+            for (id, route) in &batch_routes {
+                let route_names = route.iter().map(|id| all_names[id].clone()).collect::<Vec<_>>();
+                writeln!(out, "Route from {}:", all_names[id])?;
+                writeln!(out, "  Jumps: {}", route_names.len())?;
+                writeln!(out, "  Path: {:?}", route_names)?;
+                writeln!(out, "  Path IDs: {:?}", route)?;
+            }
+
+            if !warnings.is_empty() {
+                writeln!(out, "Warnings ({}):", warnings.len())?;
+                for warning in warnings {
+                    writeln!(out, "  {}", warning)?;
+                }
+            }
+        }
+        OutputFormat::Report => {
+            let mut report = build_report(graph, start_id, result, warnings);
+            report.elapsed_seconds = search_elapsed.as_secs_f64();
+            report.missed_systems = missed;
+            if args.summary {
+                report.summary = Some(summarize(graph, start_id, result, args.leg_size));
+            }
+            if let Some(strategy) = strategy {
+                report.strategy = strategy.to_owned();
+            }
+            report.systems_per_second = throughput(result, search_elapsed);
+            if !batch_routes.is_empty() {
+                report.routes = batch_routes;
+                report.routes.insert(start_id, result.to_vec());
+            }
+            writeln!(out, "{}", serde_json::to_string_pretty(&report).expect("Serialization failed"))?;
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "{}", CSV_HEADER)?;
+            for row in csv_rows(&jump_records(graph, start_id, result)) {
+                writeln!(out, "{}", row)?;
+            }
+        }
+        OutputFormat::Compact => {
+            writeln!(out, "{}", compact_route(result))?;
+        }
+        OutputFormat::Ndjson => {
+            for step in steps(result, start_id, all_names) {
+                serde_json::to_writer(&mut *out, &step)?;
+                writeln!(out)?;
+            }
+        }
+        OutputFormat::Checklist => {
+            for line in checklist(graph, result, name_lookup, args.flat_output) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        OutputFormat::Dotlan => {
+            let mut route = std::iter::once(start_id).chain(result.iter().copied()).collect::<Vec<_>>();
+            route.dedup();
+            writeln!(out, "{}", dotlan_url(route.iter().map(|id| all_names[id].as_str())))?;
+        }
+        OutputFormat::Breadcrumbs => {
+            for line in breadcrumbs(result, name_lookup) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        OutputFormat::Jumps => {
+            for line in jump_lines(graph, start_id, result, name_lookup) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        OutputFormat::Html => {
+            write!(out, "{}", html_page(graph, start_id, result, name_lookup))?;
+        }
+        OutputFormat::Turns => {
+            for (position, at, next) in turns(graph, start_id, result) {
+                writeln!(out, "{}. {} -> {}", position, name_lookup[&at], name_lookup[&next])?;
+            }
+        }
+        OutputFormat::IdsJson => {
+            writeln!(out, "{}", serde_json::to_string(result).expect("Serialization failed"))?;
+        }
+        OutputFormat::Destinations => {
+            for id in destinations(graph, result, start_id) {
+                writeln!(out, "{}", id)?;
+            }
+        }
+        OutputFormat::Tsv => {
+            for (order, id) in first_visit_order(result, start_id).iter().enumerate() {
+                writeln!(out, "{}\t{}", id, order)?;
+            }
+        }
+        OutputFormat::Adjacency => unreachable!("Adjacency is written before the search"),
     }
+    out.flush()
+}
 
-    let result_names = result.iter().map(|id| name_lookup.get(id).cloned().unwrap_or_default()).collect::<Vec<_>>();
-    println!("Jumps: {}", result_names.len());
-    println!("Path: {:?}", result_names);
-    println!("Path IDs: {:?}", result);
+/// Runs every strategy that suits the region and writes how they compare, recommending the best
+/// by `metric`.
+fn write_comparison(out: &mut dyn Write, graph: &MapGraph, start_id: u32, search_options: &SearchOptions, requested: &RouteRequest, metric: CompareMetric) -> std::io::Result<()> {
+    type Strategy<'a> = Box<dyn Fn() -> Vec<u32> + Sync + 'a>;
+    let mut strategies: Vec<(&str, Strategy)> = vec![
+        ("longest", Box::new(|| find_longest_paths(graph.clone(), start_id, search_options))),
+        ("no-start-reentry", Box::new(|| find_longest_paths(graph.clone(), start_id, &SearchOptions {
+            no_start_reentry: true,
+            ..search_options.clone()
+        }))),
+        ("cover", Box::new(|| cover_route(graph, start_id, &search_options.transit_only, None))),
+        ("postman", Box::new(|| postman_route(graph, start_id, None).0)),
+    ];
+    if !algo::is_cyclic_undirected(graph) {
+        strategies.push(("open-end", Box::new(|| drop_tree_return(&find_longest_paths(graph.clone(), start_id, search_options), start_id))));
+    }
+    if !requested.targets.is_empty() {
+        strategies.push(("targets", Box::new(|| visit_targets(graph, start_id, &requested.targets, &requested.before, requested.round_trip))));
+    }
 
-    debug_graph(&graph, &result, args.system);
+    let results = strategies.par_iter().map(|(name, run)| {
+        let started = Instant::now();
+        let route = run();
+        (*name, route, started.elapsed())
+    }).collect::<Vec<_>>();
+
+    writeln!(out, "{:<18} {:>6} {:>7} {:>11} {:>8}", "Strategy", "Jumps", "Unique", "Efficiency", "Time")?;
+    for (name, route, elapsed) in &results {
+        writeln!(out, "{:<18} {:>6} {:>7} {:>11.2} {:>7.2}s", name, route.len(), unique_systems(route), unique_ratio(route), elapsed.as_secs_f64())?;
+    }
+
+    // Only strategies that cover as much as the best one are worth recommending
+    let most_unique = results.iter().map(|(_, route, _)| unique_systems(route)).max().unwrap_or_default();
+    let winner = results.iter().filter(|(_, route, _)| unique_systems(route) == most_unique).min_by(|a, b| match metric {
+        CompareMetric::Jumps => a.1.len().cmp(&b.1.len()),
+        CompareMetric::Efficiency => unique_ratio(&b.1).total_cmp(&unique_ratio(&a.1)),
+        CompareMetric::Time => a.2.cmp(&b.2),
+    }).expect("No strategies ran");
+    writeln!(out, "Recommended: {} (best {})", winner.0, metric)?;
+    Ok(())
+}
+
+/// Splits the region between pilots and writes each pilot's route.
+fn write_pilots(out: &mut dyn Write, graph: &MapGraph, start_id: u32, search_options: &SearchOptions, pilots: usize, all_names: &HashMap<u32, String>) -> std::io::Result<()> {
+    let routes = split_between_pilots(graph, start_id, pilots).par_iter()
+        .map(|systems| find_longest_paths(filter_nodes(graph, |_, n| systems.contains(&n.id)), start_id, search_options))
+        .collect::<Vec<_>>();
+    for (i, route) in routes.iter().enumerate() {
+        writeln!(out, "Pilot {}: {} jumps, {} unique systems", i + 1, route.len(), unique_systems(route))?;
+        writeln!(out, "Path: {:?}", route.iter().map(|id| &all_names[id]).collect::<Vec<_>>())?;
+    }
+    let longest = routes.iter().map(Vec::len).max().unwrap_or_default();
+    let shortest = routes.iter().map(Vec::len).min().unwrap_or_default();
+    writeln!(out, "Balance: longest {} jumps, shortest {} jumps ({:.2})", longest, shortest, shortest as f64 / longest.max(1) as f64)?;
+    Ok(())
 }